@@ -0,0 +1,463 @@
+//! Assembler for the syntax of the disassembler.
+//!
+//! Instructions are written the way their `Display` prints them, in any case:
+//!
+//! ```text
+//! ; Draws a sprite forever
+//! sprite = 20c             ; constant
+//!
+//! start:
+//!     LD I, sprite
+//!     DRW V0, V1 5
+//!     JP start
+//!     db %10000000, 40, 20, 10, 08
+//!     include "more.asm"
+//! ```
+//!
+//! Numbers are hexadecimal like in the listing, optionally prefixed by `0x`,
+//! or binary with a `%` prefix. Operands may add and subtract numbers and
+//! symbols. Labels may be used before they are defined; constants only see
+//! the symbols defined above them. Code is placed at `PROGRAM_START`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::result;
+
+use error::{Chip8Error, Result};
+use instr::Opcode;
+use spec;
+
+/// Deepest nesting of include files, to catch files including themselves
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+const MNEMONICS: [&str; 29] = ["cls", "ret", "scr", "scl", "exit", "low", "high", "audio", "scd", "scu",
+                               "jp", "call", "se", "sne", "ld", "add", "or", "and", "xor", "sub", "shr",
+                               "subn", "shl", "rnd", "drw", "skp", "sknp", "plane", "pitch"];
+
+/// Values of the labels and constants
+type Symbols = HashMap<String, i64>;
+
+/// A source line, with where it comes from
+struct Line {
+    file: String,
+    number: usize,
+    text: String,
+}
+
+impl Line {
+    fn error(&self, reason: String) -> Chip8Error {
+        Chip8Error::Syntax {
+            file: self.file.clone(),
+            line: self.number,
+            reason,
+        }
+    }
+}
+
+/// A line that produces bytes, once the symbols are known
+struct Statement<'a> {
+    line: &'a Line,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+/// Assembles a source file. Included files are looked up next to it.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>> {
+    let source = fs::read_to_string(path)?;
+    let mut lines = vec![];
+    read_lines(&source, path, 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+/// Assembles source code. Included files are looked up relative to the
+/// current directory, and errors name the source `name`.
+pub fn assemble(source: &str, name: &str) -> Result<Vec<u8>> {
+    let mut lines = vec![];
+    read_lines(source, Path::new(name), 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+/// Splits source into lines, replacing includes with the lines of the
+/// included file
+fn read_lines(source: &str, path: &Path, depth: usize, lines: &mut Vec<Line>) -> Result<()> {
+    for (index, text) in source.lines().enumerate() {
+        let line = Line {
+            file: path.display().to_string(),
+            number: index + 1,
+            text: strip_comment(text).trim().to_string(),
+        };
+
+        let included = match line.text.split_once(char::is_whitespace) {
+            Some((keyword, name)) if keyword.eq_ignore_ascii_case("include") => name.trim(),
+            _ => {
+                lines.push(line);
+                continue;
+            }
+        };
+
+        let name = included.strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+            .ok_or_else(|| line.error("include needs a quoted file name".to_string()))?;
+        if depth == MAX_INCLUDE_DEPTH {
+            return Err(line.error(format!("includes nested too deep at {}", name)));
+        }
+
+        let include_path = path.parent().unwrap_or_else(|| Path::new("")).join(name);
+        let source = fs::read_to_string(&include_path)
+            .map_err(|err| line.error(format!("cannot include {}: {}", name, err)))?;
+        read_lines(&source, &include_path, depth + 1, lines)?;
+    }
+
+    Ok(())
+}
+
+fn strip_comment(text: &str) -> &str {
+    text.split(';').next().unwrap_or_default()
+}
+
+fn assemble_lines(lines: &[Line]) -> Result<Vec<u8>> {
+    let mut symbols = HashMap::new();
+    let mut statements = vec![];
+
+    // First pass: the address of every label
+    let mut addr = spec::PROGRAM_START as i64;
+    for line in lines.iter() {
+        let mut text = line.text.as_str();
+
+        while let Some((label, rest)) = text.split_once(':') {
+            if !is_identifier(label.trim()) {
+                break;
+            }
+            define(&mut symbols, label.trim(), addr).map_err(|reason| line.error(reason))?;
+            text = rest.trim();
+        }
+
+        if let Some((name, value)) = text.split_once('=') {
+            let name = name.trim();
+            if !is_identifier(name) {
+                return Err(line.error(format!("invalid constant name: {}", name)));
+            }
+            let value = eval(value, &symbols).map_err(|reason| line.error(reason))?;
+            define(&mut symbols, name, value).map_err(|reason| line.error(reason))?;
+            continue;
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, operands.split(',').map(str::trim).collect()),
+            None => (text, vec![]),
+        };
+        let statement = Statement {
+            line,
+            mnemonic: mnemonic.to_lowercase(),
+            operands,
+        };
+
+        addr += statement.size() as i64;
+        statements.push(statement);
+    }
+
+    // Second pass: the bytes
+    let mut rom = vec![];
+    for statement in statements.iter() {
+        let bytes = statement.encode(&symbols).map_err(|reason| statement.line.error(reason))?;
+        rom.extend(bytes);
+    }
+
+    Ok(rom)
+}
+
+fn define(symbols: &mut Symbols, name: &str, value: i64) -> result::Result<(), String> {
+    if symbols.insert(name.to_string(), value).is_some() {
+        return Err(format!("{} is already defined", name));
+    }
+    Ok(())
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+impl<'a> Statement<'a> {
+    /// Bytes taken by the statement
+    fn size(&self) -> usize {
+        match self.mnemonic.as_str() {
+            "db" => self.operands.len(),
+            "dw" => self.operands.len() * 2,
+            "ld" if self.long_operand().is_some() => 4,
+            _ => 2,
+        }
+    }
+
+    /// The address of `LD I, LONG addr`
+    fn long_operand(&self) -> Option<&'a str> {
+        match self.operands.as_slice() {
+            [i, long] if i.eq_ignore_ascii_case("i") => {
+                match long.split_once(char::is_whitespace) {
+                    Some((keyword, addr)) if keyword.eq_ignore_ascii_case("long") => Some(addr),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn encode(&self, symbols: &Symbols) -> result::Result<Vec<u8>, String> {
+        match self.mnemonic.as_str() {
+            "db" => self.operands.iter().map(|operand| byte(operand, symbols)).collect(),
+            "dw" => {
+                let mut bytes = vec![];
+                for operand in self.operands.iter() {
+                    bytes.extend_from_slice(&(value(operand, 0xffff, symbols)? as u16).to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            _ => {
+                let opcode = self.instruction(symbols)?;
+                let mut bytes = opcode.encode().to_be_bytes().to_vec();
+                if let Some(addr) = self.long_operand() {
+                    bytes.extend_from_slice(&(value(addr, 0xffff, symbols)? as u16).to_be_bytes());
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    fn instruction(&self, symbols: &Symbols) -> result::Result<Opcode, String> {
+        let addr = |operand| value(operand, 0xfff, symbols).map(|addr| addr as u16);
+        let nibble = |operand| value(operand, 0xf, symbols).map(|nibble| nibble as u8);
+
+        let opcode = match (self.mnemonic.as_str(), self.operands.as_slice()) {
+            ("cls", []) => Opcode::Cls,
+            ("ret", []) => Opcode::Ret,
+            ("scr", []) => Opcode::ScrollRight,
+            ("scl", []) => Opcode::ScrollLeft,
+            ("exit", []) => Opcode::Exit,
+            ("low", []) => Opcode::LowRes,
+            ("high", []) => Opcode::HighRes,
+            ("audio", []) => Opcode::Audio,
+            ("scd", [n]) => Opcode::ScrollDown { n: nibble(n)? },
+            ("scu", [n]) => Opcode::ScrollUp { n: nibble(n)? },
+            ("jp", [v0, nnn]) if register(v0) == Some(0) => Opcode::JpV0 { nnn: addr(nnn)? },
+            ("jp", [nnn]) => Opcode::Jp { nnn: addr(nnn)? },
+            ("call", [nnn]) => Opcode::Call { nnn: addr(nnn)? },
+            ("se", [x, y]) => {
+                match register(y) {
+                    Some(y) => Opcode::SeV { x: vx(x)?, y },
+                    None => Opcode::SeB { x: vx(x)?, kk: byte(y, symbols)? },
+                }
+            }
+            ("sne", [x, y]) => {
+                match register(y) {
+                    Some(y) => Opcode::SneV { x: vx(x)?, y },
+                    None => Opcode::Sne { x: vx(x)?, kk: byte(y, symbols)? },
+                }
+            }
+            ("ld", [a, b]) => self.load(a, b, symbols)?,
+            ("add", [i, x]) if i.eq_ignore_ascii_case("i") => Opcode::AddI { x: vx(x)? },
+            ("add", [x, y]) => {
+                match register(y) {
+                    Some(y) => Opcode::AddV { x: vx(x)?, y },
+                    None => Opcode::AddB { x: vx(x)?, kk: byte(y, symbols)? },
+                }
+            }
+            ("or", [x, y]) => Opcode::Or { x: vx(x)?, y: vx(y)? },
+            ("and", [x, y]) => Opcode::And { x: vx(x)?, y: vx(y)? },
+            ("xor", [x, y]) => Opcode::Xor { x: vx(x)?, y: vx(y)? },
+            ("sub", [x, y]) => Opcode::Sub { x: vx(x)?, y: vx(y)? },
+            ("shr", [x, y]) => Opcode::Shr { x: vx(x)?, y: vx(y)? },
+            // The single operand form shifts Vx whatever the shift quirk
+            ("shr", [x]) => Opcode::Shr { x: vx(x)?, y: vx(x)? },
+            ("subn", [x, y]) => Opcode::SubN { x: vx(x)?, y: vx(y)? },
+            ("shl", [x, y]) => Opcode::Shl { x: vx(x)?, y: vx(y)? },
+            ("shl", [x]) => Opcode::Shl { x: vx(x)?, y: vx(x)? },
+            ("rnd", [x, kk]) => Opcode::Rnd { x: vx(x)?, kk: byte(kk, symbols)? },
+            // Printed as `DRW Vx, Vy n`
+            ("drw", [x, y_n]) => {
+                let (y, n) = y_n.split_once(char::is_whitespace)
+                    .ok_or_else(|| "DRW needs a sprite height".to_string())?;
+                Opcode::Drw { x: vx(x)?, y: vx(y)?, n: nibble(n.trim())? }
+            }
+            ("drw", [x, y, n]) => Opcode::Drw { x: vx(x)?, y: vx(y)?, n: nibble(n)? },
+            ("skp", [x]) => Opcode::SkpVx { x: vx(x)? },
+            ("sknp", [x]) => Opcode::SknpVx { x: vx(x)? },
+            ("plane", [n]) => Opcode::Plane { n: nibble(n)? },
+            ("pitch", [x]) => Opcode::Pitch { x: vx(x)? },
+            (mnemonic, _) if MNEMONICS.contains(&mnemonic) => {
+                return Err(format!("invalid operands for {}: {}", mnemonic.to_uppercase(), self.line.text));
+            }
+            (mnemonic, _) => return Err(format!("unknown instruction: {}", mnemonic)),
+        };
+
+        Ok(opcode)
+    }
+
+    /// The many forms of `LD`
+    fn load(&self, a: &str, b: &str, symbols: &Symbols) -> result::Result<Opcode, String> {
+        let invalid = || format!("invalid operands for LD: {}", self.line.text);
+        let a_name = a.to_lowercase();
+        let b_name = b.to_lowercase();
+
+        let opcode = match (register(a), register(b)) {
+            (Some(x), Some(y)) => Opcode::LdReg { x, y },
+            (Some(x), None) => {
+                match b_name.as_str() {
+                    "dt" => Opcode::LdVxDt { x },
+                    "k" => Opcode::LdVxK { x },
+                    "[i]" => Opcode::RestoreRegs { x },
+                    "r" => Opcode::LoadRpl { x },
+                    _ => Opcode::Ld { x, kk: byte(b, symbols)? },
+                }
+            }
+            (None, Some(x)) => {
+                match a_name.as_str() {
+                    "dt" => Opcode::LdDt { x },
+                    "st" => Opcode::LdSt { x },
+                    "f" => Opcode::LdSprite { x },
+                    "hf" => Opcode::LdBigSprite { x },
+                    "b" => Opcode::LdBCD { x },
+                    "[i]" => Opcode::SaveRegs { x },
+                    "r" => Opcode::SaveRpl { x },
+                    _ => return Err(invalid()),
+                }
+            }
+            (None, None) if a_name == "i" => {
+                match self.long_operand() {
+                    Some(_) => Opcode::LdILong,
+                    None => Opcode::LdI { nnn: value(b, 0xfff, symbols)? as u16 },
+                }
+            }
+            (None, None) if a_name == "[i]" => {
+                let (x, y) = register_range(b).ok_or_else(invalid)?;
+                Opcode::SaveRange { x, y }
+            }
+            (None, None) if b_name == "[i]" => {
+                let (x, y) = register_range(a).ok_or_else(invalid)?;
+                Opcode::LoadRange { x, y }
+            }
+            (None, None) => return Err(invalid()),
+        };
+
+        Ok(opcode)
+    }
+}
+
+/// The index of register `Vx`
+fn register(operand: &str) -> Option<usize> {
+    match operand.strip_prefix('V').or_else(|| operand.strip_prefix('v')) {
+        Some(index) if index.len() == 1 => usize::from_str_radix(index, 16).ok(),
+        _ => None,
+    }
+}
+
+fn vx(operand: &str) -> result::Result<usize, String> {
+    register(operand).ok_or_else(|| format!("expected a register: {}", operand))
+}
+
+/// The registers of `Vx - Vy`
+fn register_range(operand: &str) -> Option<(usize, usize)> {
+    let (x, y) = operand.split_once('-')?;
+    Some((register(x.trim())?, register(y.trim())?))
+}
+
+/// The value of an operand, which must be in 0..=max
+fn value(operand: &str, max: i64, symbols: &Symbols) -> result::Result<i64, String> {
+    let value = eval(operand, symbols)?;
+    if value < 0 || value > max {
+        return Err(format!("{} is out of range, at most {:x}", operand, max));
+    }
+    Ok(value)
+}
+
+fn byte(operand: &str, symbols: &Symbols) -> result::Result<u8, String> {
+    value(operand, 0xff, symbols).map(|byte| byte as u8)
+}
+
+/// Adds and subtracts the numbers and symbols of an operand
+fn eval(operand: &str, symbols: &Symbols) -> result::Result<i64, String> {
+    let mut total = 0;
+    let mut sign = 1;
+    let mut rest = operand.trim();
+
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = rest[..end].trim();
+        total += sign * term_value(term, symbols)?;
+
+        match rest[end..].chars().next() {
+            Some(op) => {
+                sign = if op == '+' { 1 } else { -1 };
+                rest = &rest[(end + 1)..];
+            }
+            None => return Ok(total),
+        }
+    }
+}
+
+fn term_value(term: &str, symbols: &Symbols) -> result::Result<i64, String> {
+    if let Some(&value) = symbols.get(term) {
+        return Ok(value);
+    }
+
+    let number = match term.strip_prefix('%') {
+        Some(digits) => i64::from_str_radix(digits, 2),
+        None => i64::from_str_radix(term.strip_prefix("0x").unwrap_or(term), 16),
+    };
+    number.map_err(|_| {
+        if term.is_empty() {
+            "missing value".to_string()
+        } else {
+            format!("unknown symbol: {}", term)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_of(source: &str) -> (usize, String) {
+        match assemble(source, "test.asm") {
+            Err(Chip8Error::Syntax { line, reason, .. }) => (line, reason),
+            other => panic!("expected a syntax error, got {:?}", other.map_err(|err| err.to_string())),
+        }
+    }
+
+    #[test]
+    fn labels_resolve_before_and_after_their_definition() {
+        let source = "start:\n    CALL sub\n    JP start\nsub:\n    RET\n";
+        assert_eq!(assemble(source, "test.asm").unwrap(), [0x22, 0x04, 0x12, 0x00, 0x00, 0xee]);
+    }
+
+    #[test]
+    fn constants_and_expressions() {
+        let source = "sprite = 20c\n    ld v0, 5 + 3\n    ld i, sprite - 2\n    LD I, end\nend:\n";
+        assert_eq!(assemble(source, "test.asm").unwrap(), [0x60, 0x08, 0xa2, 0x0a, 0xa2, 0x06]);
+    }
+
+    #[test]
+    fn data() {
+        let source = "db 1, 0x2, %11 ; bytes\ndw 1234, end\nend:\n";
+        assert_eq!(assemble(source, "test.asm").unwrap(), [0x01, 0x02, 0x03, 0x12, 0x34, 0x02, 0x07]);
+    }
+
+    #[test]
+    fn shifts_take_one_or_two_registers() {
+        let source = "    SHR V3\n    SHL V3\n    SHR V3, V4\n    SHL V3, V4\n";
+        assert_eq!(assemble(source, "test.asm").unwrap(), [0x83, 0x36, 0x83, 0x3e, 0x83, 0x46, 0x83, 0x4e]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(error_of("    LD V0, 5\n    FOO\n"), (2, "unknown instruction: foo".to_string()));
+        assert_eq!(error_of("\n    JP nowhere\n"), (2, "unknown symbol: nowhere".to_string()));
+        assert_eq!(error_of("a:\na:\n"), (2, "a is already defined".to_string()));
+        assert_eq!(error_of("    db 1ff\n"), (1, "1ff is out of range, at most ff".to_string()));
+    }
+}
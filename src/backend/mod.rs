@@ -0,0 +1,57 @@
+//! Host facing interfaces of the emulator.
+//!
+//! A `Cpu` pulls input from an `InputSource`, and pushes frames and sound to a
+//! `VideoSink` and an `AudioSink`. The SDL frontend implements all three, and
+//! the `null` module provides headless implementations.
+
+use framebuffer::Framebuffer;
+use spec;
+
+pub mod null;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+
+/// Input received from the host since the last poll
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// A key of the hex keypad went down (`true`) or up (`false`)
+    Key(usize, bool),
+    /// Stop the emulator
+    Quit,
+    /// Pause or resume execution
+    TogglePause,
+    /// Toggle the stepping debug mode
+    ToggleDebug,
+    /// Execute the next instruction while stepping
+    Continue,
+    /// Write a save state to the quick save slot
+    SaveState,
+    /// Restore the save state in the quick save slot
+    LoadState,
+    /// Start (`true`) or stop (`false`) running backwards through the
+    /// recent frames
+    Rewind(bool),
+}
+
+/// Something that can show the contents of the framebuffer
+pub trait VideoSink {
+    /// Present the current frame
+    fn present(&mut self, framebuffer: &Framebuffer);
+}
+
+/// Something that produces keypad and host input
+pub trait InputSource {
+    /// Collect all the input received since the last poll
+    fn poll(&mut self) -> Vec<Input>;
+}
+
+/// Something that can play the buzzer
+pub trait AudioSink {
+    /// Start (`true`) or stop (`false`) the tone
+    fn set_tone(&mut self, on: bool);
+
+    /// Play the XO-CHIP audio pattern at the given pitch instead of the
+    /// buzzer tone, or go back to the tone with `None`. Sinks that can only
+    /// beep may ignore it.
+    fn set_pattern(&mut self, _pattern: Option<[u8; spec::AUDIO_PATTERN_SIZE]>, _pitch: u8) {}
+}
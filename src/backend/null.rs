@@ -0,0 +1,124 @@
+//! Headless backends, for running the emulator without a window.
+
+use std::collections::VecDeque;
+
+use backend::{AudioSink, Input, InputSource, VideoSink};
+use framebuffer::Framebuffer;
+
+/// Discards every frame
+#[derive(Default)]
+pub struct NullVideo;
+
+impl VideoSink for NullVideo {
+    fn present(&mut self, _framebuffer: &Framebuffer) {}
+}
+
+/// Never produces any input
+#[derive(Default)]
+pub struct NullInput;
+
+impl InputSource for NullInput {
+    fn poll(&mut self) -> Vec<Input> {
+        vec![]
+    }
+}
+
+/// Never makes a sound
+#[derive(Default)]
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn set_tone(&mut self, _on: bool) {}
+}
+
+/// Keeps a copy of the last presented frame
+#[derive(Default)]
+pub struct MemoryVideo {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    frames: u64,
+}
+
+impl MemoryVideo {
+    pub fn new() -> MemoryVideo {
+        MemoryVideo::default()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Value of a pixel in the last presented frame
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Number of frames presented so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+impl VideoSink for MemoryVideo {
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        self.width = framebuffer.width();
+        self.height = framebuffer.height();
+        self.pixels.clear();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.pixels.push(framebuffer.get_pixel(x, y));
+            }
+        }
+        self.frames += 1;
+    }
+}
+
+/// Feeds a prepared sequence of input, one batch per poll
+#[derive(Default)]
+pub struct QueuedInput {
+    batches: VecDeque<Vec<Input>>,
+}
+
+impl QueuedInput {
+    pub fn new() -> QueuedInput {
+        QueuedInput::default()
+    }
+
+    /// Queue a batch of input to be returned by a future poll
+    pub fn push(&mut self, batch: Vec<Input>) {
+        self.batches.push_back(batch);
+    }
+}
+
+impl InputSource for QueuedInput {
+    fn poll(&mut self) -> Vec<Input> {
+        self.batches.pop_front().unwrap_or_default()
+    }
+}
+
+/// Remembers whether the tone is currently playing
+#[derive(Default)]
+pub struct MemoryAudio {
+    playing: bool,
+}
+
+impl MemoryAudio {
+    pub fn new() -> MemoryAudio {
+        MemoryAudio::default()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+}
+
+impl AudioSink for MemoryAudio {
+    fn set_tone(&mut self, on: bool) {
+        self.playing = on;
+    }
+}
@@ -0,0 +1,297 @@
+//! SDL window, keyboard, game controller and audio backends.
+
+use sdl2::{GameControllerSubsystem, Sdl};
+use sdl2::EventPump;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::render::Renderer;
+use sdl2::rect::Point;
+use sdl2::pixels::Color;
+
+use backend::{AudioSink, Input, InputSource, VideoSink};
+use framebuffer::Framebuffer;
+use gamepad::Gamepad;
+use keyboard::Keyboard;
+use spec;
+
+/// SDL window presenting the machine's framebuffer
+pub struct SdlVideo {
+    renderer: Renderer<'static>,
+    size: (u32, u32),
+    palette: [Color; 16],
+}
+
+impl SdlVideo {
+    /// Opens a window `scale` times the size of the low resolution display,
+    /// or covering the whole desktop if `fullscreen`
+    pub fn new(sdl_context: &Sdl, scale: u32, fullscreen: bool) -> SdlVideo {
+        let video_subsytem = sdl_context.video().unwrap();
+
+        let mut builder = video_subsytem.window(spec::WINDOW_NAME,
+                                                spec::DISPLAY_WIDTH * scale,
+                                                spec::DISPLAY_HEIGHT * scale);
+        builder.position_centered().opengl();
+        if fullscreen {
+            builder.fullscreen_desktop();
+        }
+        let window = builder.build().unwrap();
+        let renderer = window.renderer().build().unwrap();
+
+        let mut video = SdlVideo {
+            renderer,
+            size: (0, 0),
+            palette: [Color::RGB(0, 0, 0); 16],
+        };
+        video.set_palette(spec::DEFAULT_PALETTE);
+        video
+    }
+
+    /// Sets the colours of the pixels, as 0xRRGGBB, indexed by the mask of
+    /// lit planes
+    pub fn set_palette(&mut self, palette: [u32; 16]) {
+        for (color, rgb) in self.palette.iter_mut().zip(palette.iter()) {
+            *color = Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, *rgb as u8);
+        }
+    }
+}
+
+impl VideoSink for SdlVideo {
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        // Stretch the framebuffer over the whole window, whatever its resolution
+        let size = (framebuffer.width() as u32, framebuffer.height() as u32);
+        if size != self.size {
+            let _ = self.renderer.set_logical_size(size.0, size.1);
+            self.size = size;
+        }
+
+        self.renderer.set_draw_color(self.palette[0]);
+        self.renderer.clear();
+
+        // Group the pixels by colour, to draw each colour in one go
+        let mut points = vec![vec![]; self.palette.len()];
+        for y in 0..framebuffer.height() {
+            for x in 0..framebuffer.width() {
+                let pixel = framebuffer.get_pixel(x, y) as usize;
+                if pixel != 0 {
+                    points[pixel].push(Point::new(x as i32, y as i32));
+                }
+            }
+        }
+
+        for (color, points) in self.palette.iter().zip(points.iter()).skip(1) {
+            if !points.is_empty() {
+                self.renderer.set_draw_color(*color);
+                let _ = self.renderer.draw_points(&points[..]);
+            }
+        }
+        self.renderer.present();
+    }
+}
+
+/// Keyboard and game controller input and window events from the SDL event
+/// queue
+pub struct SdlInput {
+    event_pump: EventPump,
+    keyboard: Keyboard,
+    gamepad: Gamepad,
+    /// None if game controllers are not supported
+    controllers: Option<GameControllerSubsystem>,
+    /// The controllers plugged in, which only send events while open
+    open: Vec<GameController>,
+}
+
+impl SdlInput {
+    pub fn new(sdl_context: &Sdl, keyboard: Keyboard, gamepad: Gamepad) -> SdlInput {
+        let controllers = match sdl_context.game_controller() {
+            Ok(controllers) => Some(controllers),
+            Err(err) => {
+                eprintln!("Game controllers disabled: {}", err);
+                None
+            }
+        };
+
+        // Controllers already plugged in are announced as added too
+        SdlInput {
+            event_pump: sdl_context.event_pump().unwrap(),
+            keyboard,
+            gamepad,
+            controllers,
+            open: vec![],
+        }
+    }
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self) -> Vec<Input> {
+        let mut inputs = vec![];
+
+        for event in self.event_pump.poll_iter() {
+            let input = match event {
+                Event::Quit { .. } |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => Some(Input::Quit),
+                Event::KeyDown { keycode: Some(Keycode::Return), .. } => Some(Input::TogglePause),
+                Event::KeyDown { keycode: Some(Keycode::P), .. } => Some(Input::ToggleDebug),
+                Event::KeyDown { keycode: Some(Keycode::Space), .. } => Some(Input::Continue),
+                Event::KeyDown { keycode: Some(Keycode::F5), .. } => Some(Input::SaveState),
+                Event::KeyDown { keycode: Some(Keycode::F9), .. } => Some(Input::LoadState),
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => Some(Input::Rewind(true)),
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => Some(Input::Rewind(false)),
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(ref controllers) = self.controllers {
+                        match controllers.open(which as u32) {
+                            Ok(controller) => {
+                                println!("Controller connected: {}", controller.name());
+                                self.open.push(controller);
+                            }
+                            Err(err) => eprintln!("Cannot open controller {}: {}", which, err),
+                        }
+                    }
+                    None
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(position) = self.open.iter().position(|controller| controller.instance_id() == which) {
+                        println!("Controller disconnected: {}", self.open.remove(position).name());
+                    }
+                    inputs.extend(self.gamepad.disconnect(which).into_iter().map(|(key, down)| Input::Key(key, down)));
+                    None
+                }
+                Event::ControllerButtonDown { button: Button::Start, .. } => Some(Input::TogglePause),
+                Event::ControllerButtonDown { which, button, .. } => {
+                    self.gamepad.button(which, button, true).map(|(key, down)| Input::Key(key, down))
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    self.gamepad.button(which, button, false).map(|(key, down)| Input::Key(key, down))
+                }
+                Event::ControllerAxisMotion { which, axis, value, .. } => {
+                    inputs.extend(self.gamepad.axis(which, axis, value).into_iter().map(|(key, down)| Input::Key(key, down)));
+                    None
+                }
+                Event::KeyDown { keycode, scancode, .. } => {
+                    self.keyboard.press(keycode, scancode, true).map(|(key, down)| Input::Key(key, down))
+                }
+                Event::KeyUp { keycode, scancode, .. } => {
+                    self.keyboard.press(keycode, scancode, false).map(|(key, down)| Input::Key(key, down))
+                }
+                _ => None,
+            };
+
+            inputs.extend(input);
+        }
+
+        inputs
+    }
+}
+
+/// Generator fed to the SDL audio device: a square wave, or the XO-CHIP
+/// audio pattern when one is set
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    sample_rate: f32,
+
+    pattern: Option<[u8; spec::AUDIO_PATTERN_SIZE]>,
+    pattern_inc: f32,
+    pattern_phase: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let bits = (spec::AUDIO_PATTERN_SIZE * 8) as f32;
+
+        for x in out.iter_mut() {
+            let high = match self.pattern {
+                Some(ref pattern) => {
+                    let bit = self.pattern_phase as usize;
+                    self.pattern_phase = (self.pattern_phase + self.pattern_inc) % bits;
+                    pattern[bit / 8] >> (7 - bit % 8) & 0x01 == 1
+                }
+                None => {
+                    let high = self.phase <= 0.5;
+                    self.phase = (self.phase + self.phase_inc) % 1.0;
+                    high
+                }
+            };
+
+            *x = if high { self.volume } else { -self.volume };
+        }
+    }
+}
+
+/// Buzzer playing a square wave through the default SDL audio device
+pub struct SdlAudio {
+    device: AudioDevice<SquareWave>,
+    playing: bool,
+    pattern: Option<[u8; spec::AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+}
+
+impl SdlAudio {
+    /// Open the audio device with a tone of `frequency` Hz, at `volume`
+    /// between 0 and 1
+    pub fn new(sdl_context: &Sdl, frequency: f32, volume: f32) -> SdlAudio {
+        let audio_subsystem = sdl_context.audio().unwrap();
+
+        let desired_spec = AudioSpecDesired {
+            freq: Some(spec::AUDIO_SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio_subsystem.open_playback(None, &desired_spec, |obtained| {
+                                        SquareWave {
+                                            phase_inc: frequency / obtained.freq as f32,
+                                            phase: 0.0,
+                                            volume: volume.clamp(0.0, 1.0),
+                                            sample_rate: obtained.freq as f32,
+
+                                            pattern: None,
+                                            pattern_inc: 0.0,
+                                            pattern_phase: 0.0,
+                                        }
+                                    })
+                                    .unwrap();
+
+        SdlAudio {
+            device,
+            playing: false,
+            pattern: None,
+            pitch: spec::DEFAULT_PITCH,
+        }
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn set_tone(&mut self, on: bool) {
+        if on == self.playing {
+            return;
+        }
+
+        if on {
+            self.device.resume();
+        } else {
+            self.device.pause();
+        }
+        self.playing = on;
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; spec::AUDIO_PATTERN_SIZE]>, pitch: u8) {
+        if pattern == self.pattern && pitch == self.pitch {
+            return;
+        }
+
+        // Bits per second: 4000 at the default pitch, doubling every 48 steps
+        let rate = spec::PATTERN_BASE_RATE * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+
+        let mut wave = self.device.lock();
+        wave.pattern = pattern;
+        wave.pattern_inc = rate / wave.sample_rate;
+
+        self.pattern = pattern;
+        self.pitch = pitch;
+    }
+}
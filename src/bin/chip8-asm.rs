@@ -0,0 +1,52 @@
+extern crate chip_8;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chip_8::asm;
+use chip_8::Result;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut source = None;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown option: {}", arg);
+                usage();
+            }
+            _ if source.is_some() => {
+                eprintln!("Unexpected argument: {}", arg);
+                usage();
+            }
+            _ => source = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let source = source.unwrap_or_else(|| usage());
+    // Next to the source by default
+    let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+
+    if let Err(err) = run(&source, &output) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: chip8-asm [-o <rom>] <source>");
+    process::exit(2);
+}
+
+fn run(source: &Path, output: &Path) -> Result<()> {
+    let rom = asm::assemble_file(source)?;
+    File::create(output)?.write_all(&rom)?;
+    println!("Wrote {} bytes to {}", rom.len(), output.display());
+    Ok(())
+}
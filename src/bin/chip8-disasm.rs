@@ -0,0 +1,70 @@
+extern crate chip_8;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+
+use chip_8::cfg::Cfg;
+use chip_8::disasm;
+use chip_8::Result;
+
+/// What to print
+enum Output {
+    Listing,
+    Dot,
+    Json,
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut trace = false;
+    let mut output = Output::Listing;
+    let mut file_name = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace = true,
+            "--cfg" => {
+                output = match args.next().as_deref() {
+                    Some("dot") => Output::Dot,
+                    Some("json") => Output::Json,
+                    _ => usage(),
+                }
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown option: {}", arg);
+                usage();
+            }
+            _ if file_name.is_some() => {
+                eprintln!("Unexpected argument: {}", arg);
+                usage();
+            }
+            _ => file_name = Some(arg),
+        }
+    }
+
+    let file_name = file_name.unwrap_or_else(|| usage());
+    if let Err(err) = run(&file_name, trace, output) {
+        eprintln!("{}: {}", file_name, err);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: chip8-disasm [--trace] [--cfg <dot|json>] <rom>");
+    process::exit(2);
+}
+
+fn run(file_name: &str, trace: bool, output: Output) -> Result<()> {
+    let mut rom = Vec::new();
+    File::open(file_name)?.read_to_end(&mut rom)?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    match output {
+        Output::Listing => disasm::disassemble(&rom, trace, &mut stdout),
+        Output::Dot => Cfg::build(&rom).write_dot(&mut stdout),
+        Output::Json => Cfg::build(&rom).write_json(&mut stdout),
+    }
+}
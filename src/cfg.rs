@@ -0,0 +1,203 @@
+//! Control-flow graph of a rom.
+//!
+//! Built from the instructions the disassembler traces from the start of the
+//! program. Blocks end at every jump, call, skip and return, and returns get
+//! an edge back to each CALL of their subroutine. Jumps through `JP V0` depend
+//! on a register, so the blocks ending in one are flagged as unresolved.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use disasm::{self, Flow, Instruction};
+use error::Result;
+use instr::Opcode;
+
+/// Instructions that run one after the other, only entered at the first one
+pub struct Block {
+    pub start: u16,
+    /// The instructions, with their addresses
+    pub instructions: Vec<(u16, Instruction)>,
+    /// Ends in a `JP V0`, whose destinations are not known
+    pub unresolved: bool,
+}
+
+impl Block {
+    fn last(&self) -> &Instruction {
+        &self.instructions[self.instructions.len() - 1].1
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub from: u16,
+    pub to: u16,
+    pub flow: Flow,
+}
+
+pub struct Cfg {
+    /// Sorted by address
+    pub blocks: Vec<Block>,
+    pub edges: Vec<Edge>,
+}
+
+impl Cfg {
+    /// Builds the graph of a rom loaded at `PROGRAM_START`
+    pub fn build(rom: &[u8]) -> Cfg {
+        let instructions: BTreeMap<usize, Instruction> = disasm::trace(rom)
+            .into_iter()
+            .filter_map(|offset| disasm::fetch(rom, offset).map(|instruction| (offset, instruction)))
+            .collect();
+
+        // Blocks start at the program start, and wherever control can go
+        // other than on to the next instruction
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        for (&offset, instruction) in instructions.iter() {
+            let successors = disasm::successors(rom, offset, instruction);
+            if !falls_through(&successors) {
+                leaders.insert(offset + instruction.len);
+                leaders.extend(successors.into_iter().map(|(next, _)| next));
+            }
+        }
+
+        let mut blocks = vec![];
+        let mut edges = BTreeSet::new();
+        for &leader in leaders.iter().filter(|leader| instructions.contains_key(leader)) {
+            let mut block = Block {
+                start: disasm::address(leader),
+                instructions: vec![],
+                unresolved: false,
+            };
+
+            let mut offset = leader;
+            loop {
+                let instruction = instructions[&offset];
+                block.instructions.push((disasm::address(offset), instruction));
+
+                let successors = disasm::successors(rom, offset, &instruction);
+                offset += instruction.len;
+                if !falls_through(&successors) || leaders.contains(&offset) || !instructions.contains_key(&offset) {
+                    for (next, flow) in successors.into_iter().filter(|(next, _)| instructions.contains_key(next)) {
+                        edges.insert(Edge { from: block.start, to: disasm::address(next), flow });
+                    }
+                    break;
+                }
+            }
+
+            block.unresolved = matches!(block.last().opcode, Opcode::JpV0 { .. });
+            blocks.push(block);
+        }
+
+        let returns = return_edges(&blocks, &edges);
+        edges.extend(returns);
+
+        Cfg {
+            blocks,
+            edges: edges.into_iter().collect(),
+        }
+    }
+
+    /// Writes the graph in the Graphviz DOT language
+    pub fn write_dot(&self, w: &mut dyn Write) -> Result<()> {
+        writeln!(w, "digraph rom {{")?;
+        writeln!(w, "    node [shape=box, fontname=\"monospace\"];")?;
+
+        for block in self.blocks.iter() {
+            let mut label = String::new();
+            for &(addr, ref instruction) in block.instructions.iter() {
+                label.push_str(&format!("{:04x}: {}\\l", addr, instruction.mnemonic()));
+            }
+            let style = if block.unresolved { ", color=red, xlabel=\"unresolved\"" } else { "" };
+            writeln!(w, "    b{:03x} [label=\"{}\"{}];", block.start, label, style)?;
+        }
+
+        for edge in self.edges.iter() {
+            let style = match edge.flow {
+                Flow::Next => "",
+                Flow::Skip => " [label=\"skip\", style=dashed]",
+                Flow::Jump => " [label=\"jump\"]",
+                Flow::Call => " [label=\"call\", style=bold]",
+                Flow::Return => " [label=\"return\", style=dotted]",
+            };
+            writeln!(w, "    b{:03x} -> b{:03x}{};", edge.from, edge.to, style)?;
+        }
+
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+
+    /// Writes the graph as JSON. Addresses are numbers.
+    pub fn write_json(&self, w: &mut dyn Write) -> Result<()> {
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"blocks\": [")?;
+        for (index, block) in self.blocks.iter().enumerate() {
+            let instructions: Vec<String> = block.instructions
+                .iter()
+                .map(|&(addr, ref instruction)| {
+                    format!("{{\"addr\": {}, \"mnemonic\": \"{}\"}}", addr, instruction.mnemonic())
+                })
+                .collect();
+            let separator = if index + 1 < self.blocks.len() { "," } else { "" };
+            writeln!(w,
+                     "    {{\"start\": {}, \"unresolved\": {}, \"instructions\": [{}]}}{}",
+                     block.start,
+                     block.unresolved,
+                     instructions.join(", "),
+                     separator)?;
+        }
+        writeln!(w, "  ],")?;
+
+        writeln!(w, "  \"edges\": [")?;
+        for (index, edge) in self.edges.iter().enumerate() {
+            let separator = if index + 1 < self.edges.len() { "," } else { "" };
+            writeln!(w,
+                     "    {{\"from\": {}, \"to\": {}, \"kind\": \"{}\"}}{}",
+                     edge.from,
+                     edge.to,
+                     edge.flow.name(),
+                     separator)?;
+        }
+        writeln!(w, "  ]")?;
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+}
+
+fn falls_through(successors: &[(usize, Flow)]) -> bool {
+    matches!(successors, [(_, Flow::Next)])
+}
+
+/// Edges from the blocks ending in RET back to the instruction after each
+/// CALL of their subroutine
+fn return_edges(blocks: &[Block], edges: &BTreeSet<Edge>) -> Vec<Edge> {
+    let ends_in_ret: BTreeSet<u16> = blocks.iter()
+        .filter(|block| block.last().opcode == Opcode::Ret)
+        .map(|block| block.start)
+        .collect();
+
+    let mut returns = vec![];
+    for call in edges.iter().filter(|edge| edge.flow == Flow::Call) {
+        let return_site = edges.iter().find(|edge| edge.from == call.from && edge.flow == Flow::Next);
+        let return_site = match return_site {
+            Some(edge) => edge.to,
+            None => continue,
+        };
+
+        // The blocks of the subroutine, without following the calls it makes
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![call.to];
+        while let Some(start) = pending.pop() {
+            if !seen.insert(start) {
+                continue;
+            }
+            if ends_in_ret.contains(&start) {
+                returns.push(Edge { from: start, to: return_site, flow: Flow::Return });
+            }
+            pending.extend(edges.iter()
+                .filter(|edge| edge.from == start && edge.flow != Flow::Call)
+                .map(|edge| edge.to));
+        }
+    }
+
+    returns
+}
@@ -1,328 +1,668 @@
-use std::cmp::max;
-use std::fmt;
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::Read;
-use std::io::Seek;
-use std::io::SeekFrom;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
+use std::time::Instant;
 
-use itertools::join;
-
-use sdl2::Sdl;
-use sdl2::EventPump;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-
-use time::PreciseTime;
-
-use display::Display;
-use keyboard::Keyboard;
-use spec;
+use backend::{AudioSink, Input, InputSource, VideoSink};
+use debugger::{Action, Debugger};
+use error::Result;
+use gdb::{GdbStub, Request};
 use instr;
+use machine::Machine;
+use movie::{KeyEvent, Movie};
+use profile::Profiler;
+use rewind::Rewind;
+use spec;
+use trace::Tracer;
 
-pub struct Cpu<'a> {
+/// Drives a `Machine`, connecting it to the host through its backends
+pub struct Cpu<V: VideoSink, I: InputSource, A: AudioSink> {
     // Connected systems
-    display: Display<'a>,
-    keyboard: Keyboard,
+    machine: Machine,
+    video: V,
+    input: I,
+    audio: A,
+
+    // Clock
+    ips: u32,
+    cycle_budget: u32,
+    /// Instructions left in the frame a breakpoint stopped in
+    frame_left: Option<u32>,
+    lag: Duration,
+
+    // Rewind
+    rewind: Rewind,
+    rewinding: bool,
+
+    // Movies
+    frame: u64,
+    instructions: u64,
+    recording: Option<Movie>,
+    playback: Option<VecDeque<KeyEvent>>,
 
     // Internal state
-    last_sync: PreciseTime,
+    last_sync: Instant,
     running: bool,
     paused: bool,
     debug: bool,
-
-    // Registers
-    r_vx: [u8; 16],
-    r_i: u16,
-    r_dt: u8,
-    r_st: u8,
-    r_pc: u16,
-    r_sp: u8,
-    stack: [u16; 16],
-    mem: [u8; 4096],
+    debugger: Debugger,
+    gdb: Option<GdbStub>,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    state_path: Option<PathBuf>,
 }
 
-impl<'a> Cpu<'a> {
-    /// Initialize the CPU with all registers at 0
-    pub fn new(sdl_context: &Sdl, rom_file: &'a File) -> Cpu<'a> {
-        let mut mem = [0u8; spec::MEM_SIZE];
+impl<V: VideoSink, I: InputSource, A: AudioSink> Cpu<V, I, A> {
+    /// Connect the given machine to a set of backends
+    pub fn new(machine: Machine, video: V, input: I, audio: A) -> Cpu<V, I, A> {
+        Cpu {
+            machine,
+            video,
+            input,
+            audio,
 
-        Cpu::load_sprites(&mut mem);
-        Cpu::load_rom(&mut mem, rom_file);
+            ips: spec::DEFAULT_IPS,
+            cycle_budget: 0,
+            frame_left: None,
+            lag: Duration::from_secs(0),
 
-        Cpu {
-            display: Display::new(sdl_context),
-            keyboard: Keyboard::new(),
+            rewind: Rewind::new(spec::REWIND_SECONDS * spec::FPS),
+            rewinding: false,
 
-            last_sync: PreciseTime::now(),
+            frame: 0,
+            instructions: 0,
+            recording: None,
+            playback: None,
+
+            last_sync: Instant::now(),
             running: true,
             paused: false,
-            debug: true,
-
-            r_vx: [0; 16],
-            r_i: 0,
-            r_dt: 0,
-            r_st: 0,
-            r_pc: spec::PROGRAM_START as u16,
-            r_sp: 0,
-            stack: [0; 16],
-            mem: mem,
+            debug: false,
+            debugger: Debugger::new(),
+            gdb: None,
+            tracer: None,
+            profiler: None,
+            state_path: None,
         }
     }
 
-    /// Load the built in font sprites
-    #[cfg_attr(rustfmt, rustfmt_skip)]
-    fn load_sprites(mem: &mut [u8]) {
-        let sprites = [
-            0b11110000, 0b00100000, 0b11110000, 0b11110000, 0b10010000, 0b11110000, 0b11110000,
-            0b10010000, 0b01100000, 0b00010000, 0b00010000, 0b10010000, 0b10000000, 0b10000000,
-            0b10010000, 0b00100000, 0b11110000, 0b11110000, 0b11110000, 0b11110000, 0b11110000,
-            0b10010000, 0b00100000, 0b10000000, 0b00010000, 0b00010000, 0b00010000, 0b10010000,
-            0b11110000, 0b01110000, 0b11110000, 0b11110000, 0b00010000, 0b11110000, 0b11110000,
-
-            0b11110000, 0b11110000, 0b11110000, 0b11110000, 0b11100000, 0b11110000, 0b11110000,
-            0b00010000, 0b10010000, 0b10010000, 0b10000000, 0b10010000, 0b10000000, 0b10000000,
-            0b00100000, 0b11110000, 0b11110000, 0b10000000, 0b10010000, 0b11110000, 0b11110000,
-            0b01000000, 0b10010000, 0b00010000, 0b10000000, 0b10010000, 0b10000000, 0b10000000,
-            0b01000000, 0b11110000, 0b11110000, 0b11110000, 0b11100000, 0b11110000, 0b10000000,
-        ];
-
-        for i in 0 .. sprites.len() {
-            mem[i] = sprites[i];
-        }
+    /// Get a reference to the emulated machine
+    pub fn machine(&self) -> &Machine {
+        &self.machine
     }
 
-    /// Read the whole rom file and dumps it into memory
-    fn load_rom(mem: &mut [u8], mut rom_file: &File) {
-        let mut buf: Vec<u8> = Vec::new();
+    /// Get a mutable reference to the emulated machine
+    pub fn get_machine(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
 
-        // Ensure that we are reading from the beginning of the file
-        rom_file.seek(SeekFrom::Start(0)).unwrap();
-        rom_file.read_to_end(&mut buf).unwrap();
+    /// Get a reference to the video backend
+    pub fn video(&self) -> &V {
+        &self.video
+    }
 
-        for i in 0..buf.len() {
-            mem[i + spec::PROGRAM_START] = buf[i];
-        }
+    /// Get a reference to the audio backend
+    pub fn audio(&self) -> &A {
+        &self.audio
+    }
+
+    /// Gets the clock speed, in instructions per second
+    pub fn get_ips(&self) -> u32 {
+        self.ips
     }
 
-    /// Reads the next instruction on the rom.
-    /// The position is set by the current value of PC
-    pub fn read_instr(&mut self) -> u16 {
-        let instr = ((self.mem[self.r_pc as usize] as u16) << 8) |
-                    self.mem[self.r_pc as usize + 1] as u16;
+    /// Sets the clock speed, in instructions per second
+    pub fn set_ips(&mut self, ips: u32) {
+        self.ips = ips;
+        self.cycle_budget = 0;
+    }
 
-        self.inc_pc();
-        instr
+    /// Sets the clock speed as a fixed number of instructions per frame
+    pub fn set_instructions_per_frame(&mut self, n: u32) {
+        self.set_ips(n * spec::FPS as u32);
     }
 
-    /// Read n bytes from memory, starting at addr
-    pub fn read_mem(&mut self, addr: usize, n: usize) -> Vec<u8> {
-        self.mem[addr..(addr + n)].to_vec()
+    /// Reset the last sync time to the current time
+    pub fn reset_sync(&mut self) {
+        self.last_sync = Instant::now();
+        self.lag = Duration::from_secs(0);
     }
 
-    /// Read n bytes from memory, starting at addr
-    pub fn put_mem(&mut self, addr: usize, value: u8) {
-        self.mem[addr] = value;
+    /// Sets the file used as the quick save slot. Without one, the save and
+    /// load hotkeys do nothing.
+    pub fn set_state_path<P: AsRef<Path>>(&mut self, path: P) {
+        self.state_path = Some(path.as_ref().to_path_buf());
     }
 
-    /// Sets the PC register to a given address.
-    pub fn get_pc(&self) -> u16 {
-        self.r_pc
+    /// Sets how many frames are kept for rewinding. 0 disables rewinding.
+    pub fn set_rewind_frames(&mut self, frames: usize) {
+        self.rewind = Rewind::new(frames);
     }
 
-    /// Decrements the PC to the previous instruction
-    pub fn dec_pc(&mut self) {
-        let cur_pc = self.r_pc;
-        self.set_pc(cur_pc - 2);
+    /// Number of frames run so far
+    pub fn frame(&self) -> u64 {
+        self.frame
     }
 
-    /// Increments the PC to the next instruction
-    pub fn inc_pc(&mut self) {
-        let cur_pc = self.r_pc;
-        self.set_pc(cur_pc + 2);
+    /// Number of instructions executed so far
+    pub fn instructions(&self) -> u64 {
+        self.instructions
     }
 
-    /// Sets the PC register to a given address.
-    pub fn set_pc(&mut self, addr: u16) {
-        self.r_pc = addr;
+    /// Starts recording keypad changes into the given movie
+    pub fn record(&mut self, movie: Movie) {
+        self.recording = Some(movie);
     }
 
-    /// Increment the stack pointer and put value in the top of the stack
-    pub fn push_stack(&mut self, value: u16) {
-        self.r_sp += 1;
-        self.stack[self.r_sp as usize] = value;
+    /// Stops recording, returning the movie
+    pub fn finish_recording(&mut self) -> Option<Movie> {
+        self.recording.take()
     }
 
-    /// Gets the value at the top of the stack and then decrements the stack pointer
-    pub fn pop_stack(&mut self) -> u16 {
-        let value = self.stack[self.r_sp as usize];
-        self.r_sp -= 1;
+    /// Feeds the keypad changes of a movie to the machine, ignoring the
+    /// keypad input of the host until the movie is over. The machine should
+    /// have been set up with the seed the movie was recorded with.
+    pub fn play(&mut self, movie: Movie) {
+        self.set_ips(movie.ips);
+        self.playback = Some(movie.events.into_iter().collect());
+    }
 
-        value
+    /// Writes a save state of the machine
+    pub fn save_state(&self, w: &mut dyn Write) -> Result<()> {
+        self.machine.save_state(w)
     }
 
-    /// Sets the address in memory to a given value, where x in the given index.
-    pub fn set_mem(&mut self, reg: usize, value: u8) {
-        self.mem[reg] = value;
+    /// Restores a save state written by `save_state`
+    pub fn load_state(&mut self, r: &mut dyn Read) -> Result<()> {
+        self.machine.load_state(r)?;
+        self.cycle_budget = 0;
+        self.frame_left = None;
+        self.reset_sync();
+        Ok(())
     }
 
-    /// Gets the value of the Vx register.
-    pub fn get_vx(&self, reg: usize) -> u8 {
-        self.r_vx[reg]
+    /// Get a mutable reference to the debugger, to set breakpoints
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
     }
 
-    /// Sets the Vx register to a given value, where x in the given index.
-    pub fn set_vx(&mut self, reg: usize, value: u8) {
-        self.r_vx[reg] = value;
+    /// Hands control to a connected GDB. Execution stops until GDB resumes it.
+    pub fn attach_gdb(&mut self, gdb: GdbStub) {
+        self.gdb = Some(gdb);
+        self.set_debug(true);
     }
 
-    /// Gets the value of the Vx register.
-    pub fn get_i(&self) -> u16 {
-        self.r_i
+    /// Writes a trace record for every instruction executed from now on
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
     }
 
-    /// Sets the i register to a given value.
-    pub fn set_i(&mut self, value: u16) {
-        self.r_i = value;
+    /// Stops tracing, returning the tracer
+    pub fn finish_trace(&mut self) -> Option<Tracer> {
+        self.tracer.take()
     }
 
-    /// Gets the value of the DT register
-    pub fn get_dt(&mut self) -> u8 {
-        self.r_dt
+    /// Profiles every instruction executed from now on
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
     }
 
-    /// Decreases the DT register by 1, wrapping.
-    pub fn dec_dt(&mut self) {
-        let value = self.r_dt.checked_sub(1).unwrap_or(0);
-        self.set_dt(value);
+    /// Stops profiling, returning the profiler
+    pub fn finish_profile(&mut self) -> Option<Profiler> {
+        self.profiler.take()
     }
 
-    /// Sets the DT register to a given value
-    pub fn set_dt(&mut self, value: u8) {
-        self.r_dt = value;
+    /// Whether execution is stopped in the debugger
+    pub fn is_debugging(&self) -> bool {
+        self.debug
     }
 
-    /// Get a mutable reference to the display
-    pub fn get_display(&mut self) -> &mut Display<'a> {
-        &mut self.display
+    /// Stops execution in the debugger (`true`), or resumes it (`false`)
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+        self.reset_sync();
+        if debug {
+            self.debugger.stopped(&self.machine);
+        }
     }
 
-    /// Get a mutable reference to the keyboard
-    pub fn get_keyboard(&mut self) -> &mut Keyboard {
-        &mut self.keyboard
+    /// Whether execution is paused, outside of the debugger
+    pub fn is_paused(&self) -> bool {
+        self.paused
     }
 
-    /// Reset the last sync time to the current time
-    pub fn reset_sync(&mut self) {
-        self.last_sync = PreciseTime::now();
+    /// Pauses (`true`) or resumes (`false`) execution
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.reset_sync();
     }
 
     pub fn is_running(&self) -> bool {
         self.running
     }
 
-    /// Sleep for the necessary time to sync to the desired FPS
+    /// Sleep until the next frame is due
     pub fn sync(&mut self) {
-        let now = PreciseTime::now();
+        let sleep = frame_duration().checked_sub(self.lag).unwrap_or_default();
 
-        let ellapsed = spec::MILLI_PER_FRAME as i64 - self.last_sync.to(now).num_milliseconds();
-        let sleep = max(ellapsed, 0) as u64;
-
-        self.reset_sync();
-        thread::sleep(Duration::from_millis(sleep));
+        thread::sleep(sleep);
     }
 
-    pub fn tick(&mut self, event_pump: &mut EventPump) {
-        for event in event_pump.poll_iter() {
-            match event {
-
-                Event::Quit { .. } |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+    /// Process pending input and run the frames due since the last tick.
+    ///
+    /// Errors raised by the rom are returned with the address of the offending
+    /// instruction.
+    pub fn tick(&mut self) -> Result<()> {
+        for input in self.input.poll() {
+            match input {
+                Input::Quit => {
                     self.running = false;
                 }
-                Event::KeyDown { keycode: Some(Keycode::P), .. } => {
+                Input::TogglePause => {
+                    let paused = !self.paused;
+                    self.set_paused(paused);
+                    println!("{}", if paused { "Paused" } else { "Resumed" });
+                }
+                Input::ToggleDebug => {
                     self.debug_toggle();
                 }
-                Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-                    self.paused = false;
+                Input::Continue => {
+                    if self.debug {
+                        self.debug_step(1)?;
+                    }
+                }
+                Input::SaveState => {
+                    self.quick_save();
                 }
-                Event::KeyDown { keycode: Some(keycode), .. } => {
-                    self.keyboard.press(keycode, true);
+                // Jumping to another state would throw a movie out of sync
+                Input::LoadState | Input::Rewind(true) if self.in_movie() => {
+                    println!("States can't be restored while a movie records or plays");
                 }
-                Event::KeyUp { keycode: Some(keycode), .. } => {
-                    self.keyboard.press(keycode, false);
+                Input::LoadState => {
+                    self.quick_load();
+                }
+                Input::Rewind(state) => {
+                    self.rewinding = state;
+                }
+                Input::Key(key, state) => {
+                    self.press(key, state);
                 }
-                _ => {}
             }
         }
 
-        if !self.paused {
-            let instr = self.read_instr();
-            let cmd = instr::parse(instr);
+        self.gdb_requests()?;
+
+        if self.debug {
+            self.debug_commands()?;
+            self.reset_sync();
+        } else if self.paused {
+            self.reset_sync();
+        } else {
+            self.catch_up()?;
+        }
 
-            if self.debug {
-                println!("Read: {}", cmd);
+        if self.machine.is_halted() {
+            self.running = false;
+            if let Some(ref mut gdb) = self.gdb {
+                gdb.exited();
             }
+        }
 
-            self.dec_dt();
-            instr::execute(cmd, self);
+        self.video.present(self.machine.framebuffer());
+        self.audio.set_pattern(self.machine.audio_pattern(), self.machine.get_pitch());
+        self.audio.set_tone(!self.paused && self.machine.get_st() > 0);
 
-            if self.debug {
-                println!("Current state: {}", self);
+        self.sync();
+        Ok(())
+    }
+
+    /// Runs as many frames as the real time elapsed since the last sync calls
+    /// for. If the host falls too far behind, the extra frames are dropped.
+    fn catch_up(&mut self) -> Result<()> {
+        let now = Instant::now();
+        self.lag += now - self.last_sync;
+        self.last_sync = now;
+
+        let frame = frame_duration();
+        let mut frames = 0;
+        while self.lag >= frame {
+            if frames == spec::MAX_FRAMES_PER_TICK {
+                self.lag = Duration::from_secs(0);
+                break;
+            }
+
+            if self.rewinding {
+                self.rewind_frame()?;
+            } else {
+                self.run_frame()?;
+                // Hitting a breakpoint resets the clock. The frame it stopped
+                // in is finished when execution resumes.
+                if self.debug {
+                    break;
+                }
+                self.record_frame()?;
             }
-            self.paused = self.debug;
+            self.lag -= frame;
+            frames += 1;
         }
 
-        self.display.flush();
+        Ok(())
+    }
 
-        self.sync();
+    /// Runs one 60 Hz frame: a frame's worth of instructions at the current
+    /// clock speed, followed by a single tick of the timers. A breakpoint
+    /// stops the frame midway, and the next call finishes it.
+    pub fn run_frame(&mut self) -> Result<()> {
+        let cycles = match self.frame_left.take() {
+            Some(left) => left,
+            None => {
+                self.cycle_budget += self.ips;
+                let cycles = self.cycle_budget / spec::FPS as u32;
+                self.cycle_budget %= spec::FPS as u32;
+                cycles
+            }
+        };
+
+        for done in 0..cycles {
+            if self.machine.waiting_vblank() || self.machine.is_halted() {
+                break;
+            }
+            if self.debugger.should_break(&self.machine) {
+                self.frame_left = Some(cycles - done);
+                self.debug_toggle();
+                return Ok(());
+            }
+            self.step()?;
+        }
+
+        self.end_frame();
+        Ok(())
+    }
+
+    /// Closes a frame: ticks the timers and counts the frame
+    fn end_frame(&mut self) {
+        self.machine.tick_timers();
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.end_frame();
+        }
+        self.frame += 1;
+    }
+
+    /// Applies a keypad change from the host, recording it if a movie is
+    /// being recorded. Ignored while a movie plays.
+    fn press(&mut self, key: usize, down: bool) {
+        if self.playback.is_some() || self.machine.pressed(key) == down {
+            return;
+        }
+
+        self.machine.set_key(key, down);
+        if let Some(ref mut movie) = self.recording {
+            movie.events.push(KeyEvent {
+                frame: self.frame,
+                instruction: self.instructions,
+                key,
+                down,
+            });
+        }
+    }
+
+    /// Whether a movie is being recorded or played
+    fn in_movie(&self) -> bool {
+        self.recording.is_some() || self.playback.is_some()
     }
 
-    pub fn wait_for_input(&mut self, reg: usize) {
-        for i in 0..16 {
-            if self.keyboard.pressed(i) {
-                self.r_vx[reg] = i as u8;
-                return;
+    /// Applies the keypad changes of the movie being played that are due
+    fn feed_movie(&mut self) {
+        let finished = match self.playback {
+            Some(ref mut events) => {
+                while let Some(&event) = events.front() {
+                    if event.frame > self.frame || event.instruction > self.instructions {
+                        break;
+                    }
+                    self.machine.set_key(event.key, event.down);
+                    events.pop_front();
+                }
+                events.is_empty()
+            }
+            None => false,
+        };
+
+        if finished {
+            println!("Movie finished");
+            self.playback = None;
+        }
+    }
+
+    /// Stores the current frame in the rewind buffer
+    fn record_frame(&mut self) -> Result<()> {
+        let mut state = vec![];
+        self.machine.save_state(&mut state)?;
+        self.rewind.push(state);
+        Ok(())
+    }
+
+    /// Goes back to the previous frame in the rewind buffer, if any is left
+    fn rewind_frame(&mut self) -> Result<()> {
+        if let Some(mut state) = self.rewind.pop() {
+            self.machine.load_state(&mut state)?;
+            self.frame_left = None;
+        }
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<()> {
+        self.feed_movie();
+
+        let addr = self.machine.get_pc();
+        self.execute_next().map_err(|err| err.at(addr))?;
+        self.instructions += 1;
+        Ok(())
+    }
+
+    fn execute_next(&mut self) -> Result<()> {
+        let pc = self.machine.get_pc();
+        let instr = self.machine.read_instr()?;
+        let cmd = instr::decode(instr)?;
+
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.begin(&self.machine);
+        }
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record(pc, cmd);
+        }
+
+        instr::execute(cmd, &mut self.machine)?;
+
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.end(self.instructions, self.frame, pc, instr, cmd, &self.machine)?;
+        }
+
+        Ok(())
+    }
+
+    /// Saves to the quick save slot. Failures are reported, but do not stop
+    /// the emulator.
+    fn quick_save(&mut self) {
+        let path = match self.state_path {
+            Some(ref path) => path.clone(),
+            None => return,
+        };
+
+        let result = File::create(&path)
+            .map_err(From::from)
+            .and_then(|mut file| self.save_state(&mut file));
+        match result {
+            Ok(()) => println!("Saved state to {}", path.display()),
+            Err(err) => eprintln!("Could not save state to {}: {}", path.display(), err),
+        }
+    }
+
+    /// Restores the quick save slot. Failures are reported, and leave the
+    /// machine as it was.
+    fn quick_load(&mut self) {
+        let path = match self.state_path {
+            Some(ref path) => path.clone(),
+            None => return,
+        };
+
+        let result = File::open(&path)
+            .map_err(From::from)
+            .and_then(|mut file| self.load_state(&mut file));
+        match result {
+            Ok(()) => println!("Loaded state from {}", path.display()),
+            Err(err) => eprintln!("Could not load state from {}: {}", path.display(), err),
+        }
+    }
+
+    /// Runs the commands typed in the debugger since the last tick
+    fn debug_commands(&mut self) -> Result<()> {
+        while let Some(line) = self.debugger.poll() {
+            match self.debugger.execute(&line, &mut self.machine) {
+                Action::None => self.debugger.prompt(),
+                Action::Step(count) => self.debug_step(count)?,
+                Action::Continue => {
+                    self.debug_toggle();
+                    break;
+                }
+                Action::Quit => {
+                    self.running = false;
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Acts on the requests of the connected GDB, if any
+    fn gdb_requests(&mut self) -> Result<()> {
+        let request = match self.gdb {
+            Some(ref mut gdb) => gdb.poll(&mut self.machine, &mut self.debugger),
+            None => return Ok(()),
+        };
+
+        match request {
+            Request::None => {}
+            Request::Step => self.debug_step(1)?,
+            Request::Continue => {
+                if self.debug {
+                    self.debug_toggle();
+                }
+            }
+            Request::Interrupt => {
+                if !self.debug {
+                    self.debug_toggle();
+                }
+            }
+            Request::Detach => {
+                println!("GDB detached");
+                self.gdb = None;
+                if self.debug {
+                    self.debug_toggle();
+                }
             }
+            Request::Kill => self.running = false,
         }
-        self.dec_pc()
+
+        Ok(())
     }
 
+    /// Executes instructions while stopped in the debugger
+    fn debug_step(&mut self, count: usize) -> Result<()> {
+        for _ in 0..count {
+            if self.machine.is_halted() {
+                break;
+            }
+            self.step()?;
+
+            // Steps use up the frame a breakpoint stopped in
+            if let Some(left) = self.frame_left {
+                self.frame_left = Some(left - 1).filter(|&left| left > 0);
+                if self.frame_left.is_none() {
+                    self.end_frame();
+                }
+            }
+        }
+
+        self.debugger.stopped(&self.machine);
+        if let Some(ref mut gdb) = self.gdb {
+            gdb.stopped();
+        }
+        Ok(())
+    }
+
+    /// Stops in the debugger, or resumes execution
     pub fn debug_toggle(&mut self) {
         self.debug = !self.debug;
-        self.paused = self.debug;
         self.reset_sync();
-        println!("Stepping: {}", self.debug);
+        println!("Debugger: {}", if self.debug { "on" } else { "off" });
         if self.debug {
-            println!("Current state: {}", self);
+            self.debugger.stopped(&self.machine);
+            if let Some(ref mut gdb) = self.gdb {
+                gdb.stopped();
+            }
+        } else {
+            self.debugger.resumed();
         }
     }
 }
 
+fn frame_duration() -> Duration {
+    Duration::from_nanos(1_000_000_000 / spec::FPS as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::null::{MemoryAudio, MemoryVideo, NullAudio, NullInput, NullVideo, QueuedInput};
+
+    #[test]
+    fn breakpoint_resumes_inside_the_frame() {
+        // ADD V0, 1; JP 200
+        let mut machine = Machine::new();
+        machine.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        machine.set_dt(10);
+        let mut cpu = Cpu::new(machine, NullVideo, NullInput, NullAudio);
+        cpu.set_instructions_per_frame(10);
+        cpu.debugger().add_breakpoint(0x202);
+
+        cpu.run_frame().unwrap();
+        assert!(cpu.is_debugging());
+        assert_eq!((cpu.instructions(), cpu.frame(), cpu.machine().get_dt()), (1, 0, 10));
+
+        cpu.debugger().remove_breakpoint(0x202);
+        cpu.debug_toggle();
+        cpu.run_frame().unwrap();
+        assert_eq!((cpu.instructions(), cpu.frame(), cpu.machine().get_dt()), (10, 1, 9));
+    }
 
-impl<'a> fmt::Display for Cpu<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let r_vx = join(self.r_vx.into_iter().map(|v| format!("{:02x}", v)), ", ");
-        let stack = join(self.stack.into_iter().map(|v| format!("{:04x}", v)), ", ");
-
-
-        return write!(f,
-                      "CPU [
-    r_vx: {},
-    r_i: {:04x},
-    r_dt: {:02x},
-    r_st: {:02x},
-    r_pc: {:04x},
-    r_sp: {:02x},
-    stack: {},
-]",
-                      r_vx,
-                      self.r_i,
-                      self.r_dt,
-                      self.r_st,
-                      self.r_pc,
-                      self.r_sp,
-                      stack);
+    #[test]
+    fn tick_feeds_input_to_the_machine_and_output_to_the_backends() {
+        // LD F, V0; DRW V0, V0, 5; JP 204
+        let mut machine = Machine::new();
+        machine.load_rom(&[0xf0, 0x29, 0xd0, 0x05, 0x12, 0x04]).unwrap();
+        let mut input = QueuedInput::new();
+        input.push(vec![Input::Key(0x5, true)]);
+        input.push(vec![Input::Key(0x5, false)]);
+        let mut cpu = Cpu::new(machine, MemoryVideo::new(), input, MemoryAudio::new());
+        cpu.run_frame().unwrap();
+        cpu.get_machine().set_st(10);
+
+        cpu.set_paused(true);
+        cpu.tick().unwrap();
+        assert!(cpu.machine().pressed(0x5));
+        assert_eq!(cpu.video().frames(), 1);
+        assert_eq!(cpu.video().get_pixel(0, 0), 1);
+        assert_eq!(cpu.video().get_pixel(4, 0), 0);
+        assert!(!cpu.audio().is_playing());
+
+        cpu.set_paused(false);
+        cpu.tick().unwrap();
+        assert!(!cpu.machine().pressed(0x5));
+        assert_eq!(cpu.video().frames(), 2);
+        assert!(cpu.audio().is_playing());
     }
 }
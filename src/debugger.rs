@@ -0,0 +1,282 @@
+//! Interactive debugger.
+//!
+//! Commands are read from stdin on a separate thread, so the frontend keeps
+//! running while the debugger waits for input. Inspecting and modifying the
+//! machine happens here; running it is left to the `Cpu`, through the
+//! `Action` each command returns.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use instr::{self, Opcode};
+use machine::Machine;
+
+const HELP: &str = "\
+Commands (addresses and values in hex):
+  s, step [n]          execute n instructions (default 1)
+  n, next              step over a CALL
+  f, finish            run until the current subroutine returns
+  c, continue          resume execution
+  b, break <addr>      set a breakpoint
+  d, delete <addr>     remove a breakpoint
+  bl, breakpoints      list the breakpoints
+  r, regs              show the registers
+  x, mem <addr> [n]    dump n bytes of memory (default 16)
+  l, list [addr]       disassemble around addr (default PC)
+  set <reg> <value>    set v0-vf, i, pc, dt or st
+  poke <addr> <byte>.. write bytes to memory
+  h, help              show this help
+  q, quit              stop the emulator";
+
+/// What the `Cpu` should do after a command
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Keep waiting for commands
+    None,
+    /// Execute this many instructions, then wait for commands again
+    Step(usize),
+    /// Resume execution until a breakpoint is hit
+    Continue,
+    /// Stop the emulator
+    Quit,
+}
+
+/// Breakpoints and the command line of the debugger
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    /// One-off breakpoint set by `next`
+    temp_break: Option<u16>,
+    /// Stack depth `finish` runs until the machine returns below of
+    step_out: Option<u8>,
+    /// Don't stop on the breakpoint execution resumes from
+    resuming: bool,
+    commands: Option<Receiver<String>>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    /// Sets a breakpoint at addr
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Removes the breakpoint at addr. Returns false if there was none.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Takes the next command line typed on stdin, if any
+    pub fn poll(&mut self) -> Option<String> {
+        self.commands.get_or_insert_with(read_stdin).try_recv().ok()
+    }
+
+    /// Prints the prompt
+    pub fn prompt(&self) {
+        print!("(chip-8) ");
+        let _ = io::stdout().flush();
+    }
+
+    /// Called when execution stops, to show where
+    pub fn stopped(&mut self, machine: &Machine) {
+        self.temp_break = None;
+        self.step_out = None;
+        println!("{}", disassemble(machine, machine.get_pc(), machine.get_pc()));
+        self.prompt();
+    }
+
+    /// Called when execution resumes
+    pub fn resumed(&mut self) {
+        self.resuming = true;
+    }
+
+    /// Whether execution should stop before the instruction at PC
+    pub fn should_break(&mut self, machine: &Machine) -> bool {
+        let pc = machine.get_pc();
+        let resuming = self.resuming;
+        self.resuming = false;
+
+        let hit = self.breakpoints.contains(&pc) && !resuming;
+        let stepped_over = self.temp_break == Some(pc);
+        let stepped_out = self.step_out.is_some_and(|depth| machine.get_sp() < depth);
+
+        if hit {
+            println!("Breakpoint at {:04x}", pc);
+        }
+        hit || stepped_over || stepped_out
+    }
+
+    /// Runs a command line, returning what the `Cpu` should do next
+    pub fn execute(&mut self, line: &str, machine: &mut Machine) -> Action {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match self.run(&words, machine) {
+            Ok(action) => action,
+            Err(msg) => {
+                println!("{}", msg);
+                Action::None
+            }
+        }
+    }
+
+    fn run(&mut self, words: &[&str], machine: &mut Machine) -> Result<Action, String> {
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(Action::None),
+        };
+
+        match command {
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(count) => count.parse().map_err(|_| format!("Invalid count: {}", count))?,
+                    None => 1,
+                };
+                return Ok(Action::Step(count));
+            }
+            "n" | "next" => {
+                let pc = machine.get_pc();
+                if let Ok(Opcode::Call { .. }) = fetch(machine, pc) {
+                    self.temp_break = Some(pc.wrapping_add(2));
+                    return Ok(Action::Continue);
+                }
+                return Ok(Action::Step(1));
+            }
+            "f" | "finish" => {
+                if machine.get_sp() == 0 {
+                    return Err("Not in a subroutine".to_string());
+                }
+                self.step_out = Some(machine.get_sp());
+                return Ok(Action::Continue);
+            }
+            "c" | "continue" => return Ok(Action::Continue),
+            "b" | "break" => {
+                let addr = parse_hex(args.first())?;
+                self.add_breakpoint(addr);
+                println!("Breakpoint at {:04x}", addr);
+            }
+            "d" | "delete" => {
+                let addr = parse_hex(args.first())?;
+                if !self.remove_breakpoint(addr) {
+                    return Err(format!("No breakpoint at {:04x}", addr));
+                }
+            }
+            "bl" | "breakpoints" => {
+                for addr in self.breakpoints.iter() {
+                    println!("{}", disassemble(machine, *addr, machine.get_pc()));
+                }
+            }
+            "r" | "regs" => println!("{}", machine),
+            "x" | "mem" => {
+                let addr = parse_hex(args.first())? as usize;
+                let len = match args.get(1) {
+                    Some(len) => len.parse().map_err(|_| format!("Invalid length: {}", len))?,
+                    None => 16,
+                };
+                let bytes = machine.read_mem(addr, len).map_err(|err| err.to_string())?;
+                for (row, chunk) in bytes.chunks(16).enumerate() {
+                    let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+                    println!("{:04x}: {}", addr + row * 16, hex.join(" "));
+                }
+            }
+            "l" | "list" => {
+                let pc = machine.get_pc();
+                let center = match args.first() {
+                    Some(_) => parse_hex(args.first())?,
+                    None => pc,
+                };
+                let start = center.saturating_sub(8);
+                for addr in (start..center.saturating_add(12)).step_by(2) {
+                    if machine.read_mem(addr as usize, 2).is_err() {
+                        break;
+                    }
+                    let mark = if self.breakpoints.contains(&addr) { "*" } else { " " };
+                    println!("{}{}", mark, disassemble(machine, addr, pc));
+                }
+            }
+            "set" => {
+                let reg = args.first().ok_or("set needs a register")?;
+                let value = parse_hex(args.get(1))?;
+                set_register(machine, reg, value)?;
+            }
+            "poke" => {
+                let addr = parse_hex(args.first())? as usize;
+                for (offset, byte) in args[1..].iter().enumerate() {
+                    let value = parse_hex(Some(byte))?;
+                    machine.set_mem(addr + offset, value as u8).map_err(|err| err.to_string())?;
+                }
+            }
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return Ok(Action::Quit),
+            _ => return Err(format!("Unknown command: {} (try help)", command)),
+        }
+
+        Ok(Action::None)
+    }
+}
+
+/// One line of disassembly: address, raw word and mnemonic, marking the
+/// instruction at PC
+pub fn disassemble(machine: &Machine, addr: u16, pc: u16) -> String {
+    let marker = if addr == pc { "=>" } else { "  " };
+    match machine.read_mem(addr as usize, 2) {
+        Ok(bytes) => {
+            let raw = ((bytes[0] as u16) << 8) | bytes[1] as u16;
+            match instr::decode(raw) {
+                Ok(cmd) => format!("{} {:04x}: {:#}", marker, addr, cmd),
+                Err(_) => format!("{} {:04x}: {:04x} - ??", marker, addr, raw),
+            }
+        }
+        Err(_) => format!("{} {:04x}: out of memory", marker, addr),
+    }
+}
+
+fn fetch(machine: &Machine, addr: u16) -> Result<Opcode, String> {
+    let bytes = machine.read_mem(addr as usize, 2).map_err(|err| err.to_string())?;
+    instr::decode(((bytes[0] as u16) << 8) | bytes[1] as u16).map_err(|err| err.to_string())
+}
+
+fn parse_hex(word: Option<&&str>) -> Result<u16, String> {
+    let word = word.ok_or("Missing value")?;
+    let digits = word.trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex value: {}", word))
+}
+
+fn set_register(machine: &mut Machine, reg: &str, value: u16) -> Result<(), String> {
+    let reg = reg.to_lowercase();
+    match reg.as_str() {
+        "i" => machine.set_i(value),
+        "pc" => machine.set_pc(value),
+        "dt" => machine.set_dt(value as u8),
+        "st" => machine.set_st(value as u8),
+        _ => {
+            let index = reg.strip_prefix('v')
+                .and_then(|index| usize::from_str_radix(index, 16).ok())
+                .filter(|&index| index < 16)
+                .ok_or_else(|| format!("Unknown register: {}", reg))?;
+            machine.set_vx(index, value as u8);
+        }
+    }
+
+    Ok(())
+}
+
+/// Forwards the lines typed on stdin
+fn read_stdin() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let sent = line.ok().is_some_and(|line| sender.send(line).is_ok());
+            if !sent {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
@@ -0,0 +1,324 @@
+//! Rom disassembler.
+//!
+//! Produces a listing in the syntax of the instructions' `Display`, with the
+//! address and raw bytes of every line in a comment. Words that are not
+//! instructions, or that would not assemble back to the same bytes, are
+//! listed as `db` data.
+//!
+//! By default every word is taken as an instruction if it decodes. Tracing
+//! instead follows the control flow from the start of the program, and only
+//! what can be reached is listed as code. Jumps into a table (`JP V0`) cannot
+//! be followed, so their targets show up as data.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use error::Result;
+use instr::{self, Opcode};
+use spec;
+
+/// Most bytes on a `db` line
+const DATA_PER_LINE: usize = 8;
+
+/// A line of the listing
+enum Item {
+    Code(Instruction),
+    Data(usize),
+}
+
+/// An instruction of a rom
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    /// The address in the second word of `LD I, LONG`
+    pub long: Option<u16>,
+    /// Length in bytes
+    pub len: usize,
+}
+
+impl Instruction {
+    /// The instruction as printed by `Display`, with the address of
+    /// `LD I, LONG`
+    pub fn mnemonic(&self) -> String {
+        match self.long {
+            Some(addr) => format!("LD I, LONG {:04x}", addr),
+            None => self.opcode.to_string(),
+        }
+    }
+}
+
+/// How control gets from an instruction to another
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Flow {
+    /// On to the next instruction
+    Next,
+    /// Over the next instruction
+    Skip,
+    Jump,
+    Call,
+    /// Back from a subroutine, to after the CALL
+    Return,
+}
+
+impl Flow {
+    pub fn name(self) -> &'static str {
+        match self {
+            Flow::Next => "next",
+            Flow::Skip => "skip",
+            Flow::Jump => "jump",
+            Flow::Call => "call",
+            Flow::Return => "return",
+        }
+    }
+}
+
+/// Writes the listing of a rom loaded at `PROGRAM_START`
+pub fn disassemble(rom: &[u8], trace: bool, w: &mut dyn Write) -> Result<()> {
+    let starts = if trace { self::trace(rom) } else { sweep(rom) };
+
+    // Only instructions that don't overlap another one are listed as code
+    let mut code = BTreeMap::new();
+    for &offset in starts.iter() {
+        if let Some(instruction) = fetch(rom, offset) {
+            if starts.range((offset + 1)..(offset + instruction.len)).next().is_none() {
+                code.insert(offset, instruction);
+            }
+        }
+    }
+
+    let mut calls = BTreeSet::new();
+    let mut jumps = BTreeSet::new();
+    for instruction in code.values() {
+        match instruction.opcode {
+            Opcode::Call { nnn } => {
+                calls.insert(nnn);
+            }
+            Opcode::Jp { nnn } | Opcode::JpV0 { nnn } => {
+                jumps.insert(nnn);
+            }
+            _ => {}
+        }
+    }
+
+    // Data runs are split at jump targets, so those can get a label too
+    let mut items = vec![];
+    let mut offset = 0;
+    while offset < rom.len() {
+        if let Some(&instruction) = code.get(&offset) {
+            items.push((offset, Item::Code(instruction)));
+            offset += instruction.len;
+            continue;
+        }
+
+        let mut len = 1;
+        while offset + len < rom.len() && len < DATA_PER_LINE && !code.contains_key(&(offset + len)) &&
+              !is_target(&calls, &jumps, offset + len) {
+            len += 1;
+        }
+        items.push((offset, Item::Data(len)));
+        offset += len;
+    }
+
+    let mut labels = BTreeMap::new();
+    for &(offset, _) in items.iter() {
+        let addr = address(offset);
+        if calls.contains(&addr) {
+            labels.insert(addr, format!("sub_{:03x}", addr));
+        } else if jumps.contains(&addr) {
+            labels.insert(addr, format!("label_{:03x}", addr));
+        }
+    }
+
+    for (offset, item) in items {
+        let addr = address(offset);
+        if let Some(label) = labels.get(&addr) {
+            writeln!(w, "{}:", label)?;
+        }
+
+        match item {
+            Item::Code(instruction) => {
+                let raw: Vec<String> = rom[offset..(offset + instruction.len)]
+                    .chunks(2)
+                    .map(|word| format!("{:02x}{:02x}", word[0], word[1]))
+                    .collect();
+                writeln!(w, "    {:<34}; {:04x}: {}", mnemonic(&instruction, &labels), addr, raw.join(" "))?;
+            }
+            Item::Data(len) => {
+                let bytes: Vec<String> = rom[offset..(offset + len)].iter().map(|b| format!("{:02x}", b)).collect();
+                writeln!(w, "    {:<34}; {:04x}", format!("db {}", bytes.join(", ")), addr)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The instruction at offset in the rom. Words that would not assemble back
+/// to the same bytes are not instructions.
+pub fn fetch(rom: &[u8], offset: usize) -> Option<Instruction> {
+    let raw = word(rom, offset)?;
+    let opcode = instr::decode(raw).ok().filter(|opcode| opcode.encode() == raw)?;
+
+    match opcode {
+        Opcode::LdILong => word(rom, offset + 2).map(|addr| Instruction { opcode, long: Some(addr), len: 4 }),
+        _ => Some(Instruction { opcode, long: None, len: 2 }),
+    }
+}
+
+fn word(rom: &[u8], offset: usize) -> Option<u16> {
+    rom.get(offset..(offset + 2)).map(|bytes| (bytes[0] as u16) << 8 | bytes[1] as u16)
+}
+
+/// Where control can go after the instruction at offset, as offsets in the
+/// rom. Returns and jumps through `JP V0` have no known destination.
+pub fn successors(rom: &[u8], offset: usize, instruction: &Instruction) -> Vec<(usize, Flow)> {
+    let next = offset + instruction.len;
+
+    match instruction.opcode {
+        Opcode::Ret | Opcode::Exit | Opcode::JpV0 { .. } => vec![],
+        Opcode::Jp { nnn } => rom_offset(rom, nnn).map(|target| (target, Flow::Jump)).into_iter().collect(),
+        Opcode::Call { nnn } => {
+            let mut successors: Vec<_> = rom_offset(rom, nnn).map(|target| (target, Flow::Call)).into_iter().collect();
+            successors.push((next, Flow::Next));
+            successors
+        }
+        Opcode::SeB { .. } | Opcode::Sne { .. } | Opcode::SeV { .. } | Opcode::SneV { .. } |
+        Opcode::SkpVx { .. } | Opcode::SknpVx { .. } => {
+            // Skipping over `LD I, LONG` skips both of its words
+            let skipped = if word(rom, next) == Some(0xf000) { 4 } else { 2 };
+            vec![(next, Flow::Next), (next + skipped, Flow::Skip)]
+        }
+        _ => vec![(next, Flow::Next)],
+    }
+}
+
+/// Every word that decodes, one after the other
+fn sweep(rom: &[u8]) -> BTreeSet<usize> {
+    let mut starts = BTreeSet::new();
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        match fetch(rom, offset) {
+            Some(instruction) => {
+                starts.insert(offset);
+                offset += instruction.len;
+            }
+            None => offset += 2,
+        }
+    }
+
+    starts
+}
+
+/// Offsets of the instructions reachable from the start of the program
+pub fn trace(rom: &[u8]) -> BTreeSet<usize> {
+    let mut starts = BTreeSet::new();
+    let mut pending = vec![0];
+
+    while let Some(offset) = pending.pop() {
+        if starts.contains(&offset) {
+            continue;
+        }
+        let instruction = match fetch(rom, offset) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        starts.insert(offset);
+        pending.extend(successors(rom, offset, &instruction).into_iter().map(|(next, _)| next));
+    }
+
+    starts
+}
+
+/// The offset in the rom of an address, if it is in the rom
+pub fn rom_offset(rom: &[u8], addr: u16) -> Option<usize> {
+    (addr as usize).checked_sub(spec::PROGRAM_START).filter(|&offset| offset < rom.len())
+}
+
+/// The address of an offset in the rom
+pub fn address(offset: usize) -> u16 {
+    (spec::PROGRAM_START + offset) as u16
+}
+
+fn is_target(calls: &BTreeSet<u16>, jumps: &BTreeSet<u16>, offset: usize) -> bool {
+    let addr = address(offset);
+    calls.contains(&addr) || jumps.contains(&addr)
+}
+
+/// The instruction's mnemonic, with labels in place of the addresses that
+/// have one
+fn mnemonic(instruction: &Instruction, labels: &BTreeMap<u16, String>) -> String {
+    let target = |addr: u16| labels.get(&addr).cloned().unwrap_or_else(|| format!("{:03x}", addr));
+
+    match instruction.opcode {
+        Opcode::Jp { nnn } => format!("JP {}", target(nnn)),
+        Opcode::Call { nnn } => format!("CALL {}", target(nnn)),
+        Opcode::JpV0 { nnn } => format!("JP V0, {}", target(nnn)),
+        _ => instruction.mnemonic(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asm;
+
+    /// A call, a jump to itself, sprite data, an XO-CHIP long load and an
+    /// odd byte at the end
+    const PROGRAM: [u8; 19] = [
+        0x22, 0x08, 0x12, 0x02, 0xaa, 0xf0, 0x00, 0xfd, 0xa2, 0x04,
+        0xd0, 0x11, 0xf0, 0x00, 0x02, 0x04, 0x00, 0xee, 0x80,
+    ];
+
+    fn listing(rom: &[u8], trace: bool) -> String {
+        let mut listing = vec![];
+        disassemble(rom, trace, &mut listing).unwrap();
+        String::from_utf8(listing).unwrap()
+    }
+
+    fn assert_round_trip(rom: &[u8], trace: bool) {
+        let listing = listing(rom, trace);
+        assert_eq!(asm::assemble(&listing, "listing.asm").unwrap(), rom, "{}", listing);
+    }
+
+    /// Bytes from a fixed xorshift, for a rom of everything
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn program_round_trips() {
+        assert_round_trip(&PROGRAM, false);
+        assert_round_trip(&PROGRAM, true);
+    }
+
+    #[test]
+    fn every_word_round_trips() {
+        for word in 0..=0xffffu16 {
+            assert_round_trip(&word.to_be_bytes(), false);
+        }
+    }
+
+    #[test]
+    fn noise_round_trips() {
+        let rom = noise(spec::MEM_SIZE - spec::PROGRAM_START);
+        assert_round_trip(&rom, false);
+        assert_round_trip(&rom, true);
+    }
+
+    #[test]
+    fn trace_lists_unreached_words_as_data() {
+        let listing = listing(&PROGRAM, true);
+        assert!(listing.contains("sub_208:\n    LD I, 204"), "{}", listing);
+        assert!(listing.contains("label_202:\n    JP label_202"), "{}", listing);
+        assert!(listing.contains("db aa, f0, 00, fd"), "{}", listing);
+    }
+}
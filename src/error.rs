@@ -0,0 +1,91 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+pub type Result<T> = result::Result<T, Chip8Error>;
+
+/// Everything that can go wrong while loading or running a rom
+#[derive(Debug)]
+pub enum Chip8Error {
+    /// The word at `addr` is not a known instruction
+    InvalidOpcode { addr: u16, raw: u16 },
+    /// CALL at `addr` with the stack already full
+    StackOverflow { addr: u16 },
+    /// RET at `addr` with an empty stack
+    StackUnderflow { addr: u16 },
+    /// The instruction at `addr` accessed memory past its end, at `index`
+    MemoryOutOfBounds { addr: u16, index: usize },
+    /// The rom does not fit in the memory available for programs
+    RomTooLarge { size: usize, max: usize },
+    /// The save state is corrupt, or was written by an incompatible version
+    InvalidState { reason: &'static str },
+    /// The movie file cannot be parsed at `line`
+    InvalidMovie { line: usize, reason: &'static str },
+    /// The source file `file` cannot be assembled at `line`
+    Syntax { file: String, line: usize, reason: String },
+    /// The rom settings in `file` cannot be read
+    InvalidConfig { file: String, reason: String },
+    Io(io::Error),
+}
+
+impl Chip8Error {
+    /// Attach the address of the offending instruction to the error.
+    ///
+    /// Errors are raised deep inside decoding and execution, where the address
+    /// of the current instruction is not known; the machine fills it in once
+    /// the error reaches the fetch loop.
+    pub fn at(self, addr: u16) -> Chip8Error {
+        match self {
+            Chip8Error::InvalidOpcode { raw, .. } => Chip8Error::InvalidOpcode { addr, raw },
+            Chip8Error::StackOverflow { .. } => Chip8Error::StackOverflow { addr },
+            Chip8Error::StackUnderflow { .. } => Chip8Error::StackUnderflow { addr },
+            Chip8Error::MemoryOutOfBounds { index, .. } => {
+                Chip8Error::MemoryOutOfBounds { addr, index }
+            }
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::InvalidOpcode { addr, raw } => {
+                write!(f, "invalid opcode {:04x} at {:04x}", raw, addr)
+            }
+            Chip8Error::StackOverflow { addr } => write!(f, "stack overflow at {:04x}", addr),
+            Chip8Error::StackUnderflow { addr } => write!(f, "stack underflow at {:04x}", addr),
+            Chip8Error::MemoryOutOfBounds { addr, index } => {
+                write!(f, "memory access out of bounds ({:04x}) at {:04x}", index, addr)
+            }
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "rom too large: {} bytes, at most {} fit in memory", size, max)
+            }
+            Chip8Error::InvalidState { reason } => write!(f, "invalid save state: {}", reason),
+            Chip8Error::InvalidMovie { line, reason } => {
+                write!(f, "invalid movie, line {}: {}", line, reason)
+            }
+            Chip8Error::Syntax { ref file, line, ref reason } => {
+                write!(f, "{}:{}: {}", file, line, reason)
+            }
+            Chip8Error::InvalidConfig { ref file, ref reason } => write!(f, "{}: {}", file, reason),
+            Chip8Error::Io(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Chip8Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(err: io::Error) -> Chip8Error {
+        Chip8Error::Io(err)
+    }
+}
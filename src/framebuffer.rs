@@ -0,0 +1,166 @@
+use std::io::{Read, Write};
+
+use error::Result;
+use spec;
+use state;
+
+/// Pixel buffer written by the interpreter.
+///
+/// It holds no rendering state; frontends read it back to present a frame.
+/// The buffer is 64x32 in low resolution mode and 128x64 in the SUPER-CHIP
+/// high resolution mode.
+///
+/// Each pixel is a bitmask of the XO-CHIP bitplanes it is lit in, so plain
+/// Chip-8 programs, which only ever draw to the first plane, see 0 or 1. Frontends
+/// use the mask as an index into their colour palette.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    planes: u8,
+}
+
+impl Framebuffer {
+    pub fn new() -> Framebuffer {
+        let width = spec::DISPLAY_WIDTH as usize;
+        let height = spec::DISPLAY_HEIGHT as usize;
+
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0u8; width * height],
+            planes: 0x1,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Whether the buffer is in high resolution mode
+    pub fn hires(&self) -> bool {
+        self.width == spec::HIRES_WIDTH as usize
+    }
+
+    /// Switches between low and high resolution, clearing the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        let (width, height) = if hires {
+            (spec::HIRES_WIDTH, spec::HIRES_HEIGHT)
+        } else {
+            (spec::DISPLAY_WIDTH, spec::DISPLAY_HEIGHT)
+        };
+
+        self.width = width as usize;
+        self.height = height as usize;
+        self.pixels = vec![0u8; self.width * self.height];
+    }
+
+    /// Bitmask of the planes affected by drawing, clearing and scrolling
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// Selects the planes affected by drawing, clearing and scrolling
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & 0x0f;
+    }
+
+    /// Bitmask of the planes the pixel is lit in
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Flips a pixel of a single plane. Returns true if the pixel was erased.
+    pub fn flip(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let pixel = &mut self.pixels[y * self.width + x];
+        let erased = *pixel & plane != 0;
+
+        *pixel ^= plane;
+        erased
+    }
+
+    /// Clears the selected planes
+    pub fn clear(&mut self) {
+        let keep = !self.planes;
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= keep;
+        }
+    }
+
+    /// Scrolls the screen down by n lines
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    /// Scrolls the screen up by n lines
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    /// Scrolls the screen right by n pixels
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    /// Scrolls the screen left by n pixels
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    /// Writes the resolution, the selected planes and the pixels
+    pub fn save_state(&self, w: &mut dyn Write) -> Result<()> {
+        state::write_bool(w, self.hires())?;
+        state::write_u8(w, self.planes)?;
+        w.write_all(&self.pixels)?;
+        Ok(())
+    }
+
+    /// Reads a framebuffer written by `save_state`
+    pub fn load_state(r: &mut dyn Read) -> Result<Framebuffer> {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set_hires(state::read_bool(r)?);
+        framebuffer.set_planes(state::read_u8(r)?);
+
+        let pixels = state::read_bytes(r, framebuffer.pixels.len())?;
+        if pixels.iter().any(|&pixel| pixel > 0x0f) {
+            return Err(state::invalid("corrupt framebuffer"));
+        }
+        framebuffer.pixels = pixels;
+
+        Ok(framebuffer)
+    }
+
+    /// Moves the selected planes by (dx, dy), filling the uncovered area with
+    /// blank pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let planes = self.planes;
+        let old = self.pixels.clone();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let in_bounds = src_x >= 0 && src_x < self.width as isize && src_y >= 0 &&
+                                src_y < self.height as isize;
+                let moved = if in_bounds {
+                    old[src_y as usize * self.width + src_x as usize]
+                } else {
+                    0
+                };
+
+                let pixel = &mut self.pixels[y * self.width + x];
+                *pixel = (*pixel & !planes) | (moved & planes);
+            }
+        }
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Framebuffer {
+        Framebuffer::new()
+    }
+}
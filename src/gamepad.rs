@@ -0,0 +1,193 @@
+use sdl2::controller::{Axis, Button};
+
+use keymap::{KeyMap, PAD_PREFIX};
+
+/// How far a stick or trigger must be pushed to count as pressed, as a
+/// fraction of its travel
+pub const DEFAULT_THRESHOLD: f32 = 0.5;
+
+/// The button the emulator keeps for pausing
+const RESERVED: Button = Button::Start;
+
+/// Buttons by the names SDL gives them in controller mappings
+const BUTTON_NAMES: [(&str, Button); 15] = [
+    ("a", Button::A),
+    ("b", Button::B),
+    ("x", Button::X),
+    ("y", Button::Y),
+    ("back", Button::Back),
+    ("guide", Button::Guide),
+    ("start", Button::Start),
+    ("leftstick", Button::LeftStick),
+    ("rightstick", Button::RightStick),
+    ("leftshoulder", Button::LeftShoulder),
+    ("rightshoulder", Button::RightShoulder),
+    ("dpup", Button::DPadUp),
+    ("dpdown", Button::DPadDown),
+    ("dpleft", Button::DPadLeft),
+    ("dpright", Button::DPadRight),
+];
+
+/// Axes by the names SDL gives them in controller mappings
+const AXIS_NAMES: [(&str, Axis); 6] = [
+    ("leftx", Axis::LeftX),
+    ("lefty", Axis::LeftY),
+    ("rightx", Axis::RightX),
+    ("righty", Axis::RightY),
+    ("lefttrigger", Axis::TriggerLeft),
+    ("righttrigger", Axis::TriggerRight),
+];
+
+/// A controller input that works as a button
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PadInput {
+    Button(Button),
+    /// A stick or trigger pushed past the threshold, towards positive values
+    /// (`true`) or negative ones (`false`)
+    Axis(Axis, bool),
+}
+
+impl PadInput {
+    /// Looks up an input by name, as in `pad:dpup` or `pad:leftx-`
+    pub fn from_name(name: &str) -> Option<PadInput> {
+        let name = name.strip_prefix(PAD_PREFIX)?;
+        if let Some(&(_, button)) = BUTTON_NAMES.iter().find(|&&(button, _)| button == name) {
+            return Some(PadInput::Button(button));
+        }
+
+        let (axis, positive) = match name.split_at(name.len().saturating_sub(1)) {
+            (axis, "+") => (axis, true),
+            (axis, "-") => (axis, false),
+            _ => return None,
+        };
+        AXIS_NAMES.iter()
+            .find(|&&(name, _)| name == axis)
+            .map(|&(_, axis)| PadInput::Axis(axis, positive))
+    }
+}
+
+/// Translates game controller input into the machine's hex keypad
+pub struct Gamepad {
+    /// The controller inputs of each hex key
+    inputs: [Vec<PadInput>; 16],
+    /// Axis value a stick or trigger must reach to count as pressed
+    threshold: i16,
+    /// Inputs held down, with the controller they are on and the hex key
+    /// they pressed
+    held: Vec<(i32, PadInput, usize)>,
+}
+
+impl Default for Gamepad {
+    /**
+     * Maps the D-pad and the left stick to the keys most roms move with, and
+     * the face and shoulder buttons around them
+     *  D-pad/stick up    5    A    6    X    C    left shoulder   1
+     *  D-pad/stick down  8    B    4    Y    D    right shoulder  3
+     *  D-pad/stick left  7
+     *  D-pad/stick right 9
+     */
+    fn default() -> Gamepad {
+        let bindings = [
+            (0x5, vec![PadInput::Button(Button::DPadUp), PadInput::Axis(Axis::LeftY, false)]),
+            (0x8, vec![PadInput::Button(Button::DPadDown), PadInput::Axis(Axis::LeftY, true)]),
+            (0x7, vec![PadInput::Button(Button::DPadLeft), PadInput::Axis(Axis::LeftX, false)]),
+            (0x9, vec![PadInput::Button(Button::DPadRight), PadInput::Axis(Axis::LeftX, true)]),
+            (0x6, vec![PadInput::Button(Button::A)]),
+            (0x4, vec![PadInput::Button(Button::B)]),
+            (0xc, vec![PadInput::Button(Button::X)]),
+            (0xd, vec![PadInput::Button(Button::Y)]),
+            (0x1, vec![PadInput::Button(Button::LeftShoulder)]),
+            (0x3, vec![PadInput::Button(Button::RightShoulder)]),
+        ];
+
+        let mut inputs: [Vec<PadInput>; 16] = Default::default();
+        for (key, key_inputs) in bindings.iter() {
+            inputs[*key] = key_inputs.clone();
+        }
+        Gamepad {
+            inputs,
+            threshold: threshold(DEFAULT_THRESHOLD),
+            held: vec![],
+        }
+    }
+}
+
+impl Gamepad {
+    pub fn new() -> Gamepad {
+        Gamepad::default()
+    }
+
+    /// The default bindings, with the button bindings of map in place of them.
+    /// An input bound again leaves the hex key it was bound to.
+    pub fn with_map(map: &KeyMap) -> ::std::result::Result<Gamepad, String> {
+        let mut gamepad = Gamepad::new();
+        for (key, names) in map.button_bindings() {
+            let inputs = names.iter()
+                .map(|name| PadInput::from_name(name).ok_or_else(|| format!("unknown button {}", name)))
+                .collect::<::std::result::Result<Vec<PadInput>, String>>()?;
+            if let Some((name, _)) = names.iter().zip(inputs.iter()).find(|&(_, &input)| input == PadInput::Button(RESERVED)) {
+                return Err(format!("reserved button {}", name));
+            }
+            for key_inputs in gamepad.inputs.iter_mut() {
+                key_inputs.retain(|input| !inputs.contains(input));
+            }
+            gamepad.inputs[key] = inputs;
+        }
+        Ok(gamepad)
+    }
+
+    /// Sets how far sticks and triggers must be pushed to count as pressed,
+    /// as a fraction of their travel
+    pub fn set_threshold(&mut self, fraction: f32) {
+        self.threshold = threshold(fraction);
+    }
+
+    /// Follows a button of controller which going down or up. Returns the hex
+    /// key it pressed or released.
+    pub fn button(&mut self, which: i32, button: Button, down: bool) -> Option<(usize, bool)> {
+        self.set(which, PadInput::Button(button), down)
+    }
+
+    /// Follows a stick or trigger of controller which moving to value.
+    /// Returns the hex keys pressed or released by either direction.
+    pub fn axis(&mut self, which: i32, axis: Axis, value: i16) -> Vec<(usize, bool)> {
+        let positive = self.set(which, PadInput::Axis(axis, true), value >= self.threshold);
+        let negative = self.set(which, PadInput::Axis(axis, false), value <= -self.threshold);
+        positive.into_iter().chain(negative).collect()
+    }
+
+    /// Releases the hex keys held on controller which, once unplugged
+    pub fn disconnect(&mut self, which: i32) -> Vec<(usize, bool)> {
+        let inputs: Vec<PadInput> = self.held
+            .iter()
+            .filter(|&&(controller, _, _)| controller == which)
+            .map(|&(_, input, _)| input)
+            .collect();
+        inputs.into_iter().filter_map(|input| self.set(which, input, false)).collect()
+    }
+
+    /// Presses or releases an input. A hex key with several inputs stays
+    /// pressed until the last of them is released.
+    fn set(&mut self, which: i32, input: PadInput, down: bool) -> Option<(usize, bool)> {
+        let held = self.held.iter().position(|&(controller, held, _)| controller == which && held == input);
+        match (down, held) {
+            (true, None) => {
+                let key = self.inputs.iter().position(|inputs| inputs.contains(&input))?;
+                self.held.push((which, input, key));
+                Some((key, true))
+            }
+            (false, Some(position)) => {
+                let (_, _, key) = self.held.remove(position);
+                if self.held.iter().any(|&(_, _, held)| held == key) {
+                    return None;
+                }
+                Some((key, false))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn threshold(fraction: f32) -> i16 {
+    (fraction.clamp(0.0, 1.0) * i16::MAX as f32).max(1.0) as i16
+}
@@ -0,0 +1,401 @@
+//! GDB remote serial protocol stub.
+//!
+//! Lets GDB, or any frontend speaking its remote protocol, debug the program
+//! running on the machine over TCP. The stub handles the packets itself and
+//! asks the `Cpu` to step, continue or stop through a `Request`; breakpoints
+//! are shared with the built in `Debugger`.
+//!
+//! GDB has no notion of a Chip-8, so the stub describes the registers in a
+//! target description (`target.xml`), in this order: V0 to VF, I, PC, SP, DT
+//! and ST. Register values are sent little endian, as the protocol expects.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use debugger::Debugger;
+use machine::Machine;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Registers in the order of the target description
+const REGISTER_COUNT: usize = 21;
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+
+/// What the debugger on the other end asked the `Cpu` to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Nothing to do
+    None,
+    /// Execute a single instruction, then stop
+    Step,
+    /// Resume execution
+    Continue,
+    /// Stop execution
+    Interrupt,
+    /// The debugger went away; resume execution
+    Detach,
+    /// Stop the emulator
+    Kill,
+}
+
+/// A connection to GDB
+pub struct GdbStub {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    /// Whether GDB waits for a stop reply, after a step or a continue
+    running: bool,
+}
+
+impl GdbStub {
+    /// Waits for GDB to connect on the given address
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+
+        Ok(GdbStub {
+            stream,
+            buffer: vec![],
+            running: false,
+        })
+    }
+
+    /// Handles the packets received since the last poll. Packets are answered
+    /// right away, except for step and continue, which are answered by
+    /// `stopped` once the machine stops.
+    pub fn poll(&mut self, machine: &mut Machine, debugger: &mut Debugger) -> Request {
+        let mut chunk = [0u8; 1024];
+        let mut closed = false;
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    closed = true;
+                    break;
+                }
+            }
+        }
+
+        let mut request = Request::None;
+        while let Some(packet) = self.next_packet() {
+            let packet = match packet {
+                Packet::Interrupt => {
+                    request = Request::Interrupt;
+                    continue;
+                }
+                Packet::Data(packet) => packet,
+            };
+
+            match self.handle(&packet, machine, debugger) {
+                Some(reply) => self.send(&reply),
+                None => {
+                    request = match packet.as_bytes()[0] {
+                        b's' => Request::Step,
+                        b'c' => Request::Continue,
+                        b'D' => Request::Detach,
+                        _ => Request::Kill,
+                    };
+                    if request == Request::Detach {
+                        self.send("OK");
+                    }
+                    if request == Request::Step || request == Request::Continue {
+                        self.running = true;
+                    }
+                }
+            }
+        }
+
+        if closed && request != Request::Kill {
+            return Request::Detach;
+        }
+        request
+    }
+
+    /// Tells GDB the machine stopped, if it is waiting for it
+    pub fn stopped(&mut self) {
+        if self.running {
+            self.running = false;
+            self.send("S05");
+        }
+    }
+
+    /// Tells GDB the program exited
+    pub fn exited(&mut self) {
+        self.running = false;
+        self.send("W00");
+    }
+
+    /// Splits the next packet off the receive buffer, acknowledging it
+    fn next_packet(&mut self) -> Option<Packet> {
+        loop {
+            let start = self.buffer.iter().position(|&b| b == b'$' || b == 0x03)?;
+            if self.buffer[start] == 0x03 {
+                self.buffer.drain(..=start);
+                return Some(Packet::Interrupt);
+            }
+
+            // Wait for the whole packet, checksum included
+            let end = self.buffer[start..].iter().position(|&b| b == b'#')? + start;
+            if self.buffer.len() < end + 3 {
+                return None;
+            }
+
+            let data: Vec<u8> = self.buffer[(start + 1)..end].to_vec();
+            let checksum = String::from_utf8_lossy(&self.buffer[(end + 1)..(end + 3)]).to_string();
+            self.buffer.drain(..(end + 3));
+
+            if u8::from_str_radix(&checksum, 16).ok() != Some(checksum_of(&data)) {
+                self.write_raw(b"-");
+                continue;
+            }
+
+            self.write_raw(b"+");
+            return Some(Packet::Data(String::from_utf8_lossy(&data).to_string()));
+        }
+    }
+
+    /// Answers a packet. Returns None for the packets the `Cpu` has to act on.
+    fn handle(&mut self, packet: &str, machine: &mut Machine, debugger: &mut Debugger) -> Option<String> {
+        let mut chars = packet.chars();
+        let kind = match chars.next() {
+            Some(kind) => kind,
+            None => return Some(String::new()),
+        };
+        let args = chars.as_str();
+
+        let reply = match kind {
+            '?' => "S05".to_string(),
+            'g' => (0..REGISTER_COUNT).map(|reg| read_register(machine, reg)).collect(),
+            'G' => {
+                let mut rest = args;
+                for reg in 0..REGISTER_COUNT {
+                    let size = register_size(reg) * 2;
+                    if rest.len() < size || !write_register(machine, reg, &rest[..size]) {
+                        return Some("E01".to_string());
+                    }
+                    rest = &rest[size..];
+                }
+                "OK".to_string()
+            }
+            'p' => {
+                match usize::from_str_radix(args, 16) {
+                    Ok(reg) if reg < REGISTER_COUNT => read_register(machine, reg),
+                    _ => "E01".to_string(),
+                }
+            }
+            'P' => {
+                let mut parts = args.splitn(2, '=');
+                let reg = parts.next().and_then(|reg| usize::from_str_radix(reg, 16).ok());
+                match (reg, parts.next()) {
+                    (Some(reg), Some(value)) if reg < REGISTER_COUNT &&
+                                                write_register(machine, reg, value) => {
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            'm' => {
+                match parse_range(args, machine.platform().mem_size()) {
+                    Some((addr, len)) => {
+                        match machine.read_mem(addr, len) {
+                            Ok(bytes) => to_hex(&bytes),
+                            Err(_) => "E01".to_string(),
+                        }
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            'M' => {
+                let mut parts = args.splitn(2, ':');
+                let mem_size = machine.platform().mem_size();
+                let range = parts.next().and_then(|range| parse_range(range, mem_size));
+                let bytes = parts.next().and_then(from_hex);
+                match (range, bytes) {
+                    (Some((addr, len)), Some(ref bytes)) if bytes.len() == len => {
+                        let written = bytes.iter()
+                            .enumerate()
+                            .all(|(offset, &byte)| machine.set_mem(addr + offset, byte).is_ok());
+                        if written { "OK".to_string() } else { "E01".to_string() }
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            'Z' | 'z' => {
+                let mut parts = args.split(',');
+                let software = parts.next() == Some("0");
+                let addr = parts.next().and_then(|addr| u16::from_str_radix(addr, 16).ok());
+                match (software, addr) {
+                    (true, Some(addr)) => {
+                        if kind == 'Z' {
+                            debugger.add_breakpoint(addr);
+                        } else {
+                            debugger.remove_breakpoint(addr);
+                        }
+                        "OK".to_string()
+                    }
+                    // Only software breakpoints are supported
+                    _ => String::new(),
+                }
+            }
+            's' | 'c' | 'D' | 'k' => return None,
+            'H' => "OK".to_string(),
+            'q' => query(args),
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn send(&mut self, data: &str) {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.write_raw(packet.as_bytes());
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) {
+        // The socket is non blocking; retry until the reply is out
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            match self.stream.write(rest) {
+                Ok(n) => rest = &rest[n..],
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+enum Packet {
+    Data(String),
+    Interrupt,
+}
+
+/// Answers the general queries
+fn query(args: &str) -> String {
+    if args.starts_with("Supported") {
+        return "PacketSize=1000;qXfer:features:read+".to_string();
+    }
+    if args == "Attached" {
+        return "1".to_string();
+    }
+    if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+        let mut parts = range.split(',');
+        let offset = parts.next().and_then(|n| usize::from_str_radix(n, 16).ok());
+        let len = parts.next().and_then(|n| usize::from_str_radix(n, 16).ok());
+        return match (offset, len) {
+            (Some(offset), Some(len)) if offset <= TARGET_XML.len() => {
+                let end = offset.saturating_add(len).min(TARGET_XML.len());
+                let more = if end < TARGET_XML.len() { "m" } else { "l" };
+                format!("{}{}", more, &TARGET_XML[offset..end])
+            }
+            _ => "E01".to_string(),
+        };
+    }
+
+    String::new()
+}
+
+fn register_size(reg: usize) -> usize {
+    match reg {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+fn read_register(machine: &Machine, reg: usize) -> String {
+    match reg {
+        REG_I => to_hex(&machine.get_i().to_le_bytes()),
+        REG_PC => to_hex(&machine.get_pc().to_le_bytes()),
+        REG_SP => to_hex(&[machine.get_sp()]),
+        REG_DT => to_hex(&[machine.get_dt()]),
+        REG_ST => to_hex(&[machine.get_st()]),
+        _ => to_hex(&[machine.get_vx(reg)]),
+    }
+}
+
+/// Writes a register from its hex encoded value. Returns false if the value
+/// is invalid.
+fn write_register(machine: &mut Machine, reg: usize, hex: &str) -> bool {
+    let bytes = match from_hex(hex) {
+        Some(ref bytes) if bytes.len() == register_size(reg) => bytes.clone(),
+        _ => return false,
+    };
+    let word = || u16::from_le_bytes([bytes[0], bytes[1]]);
+
+    match reg {
+        REG_I => machine.set_i(word()),
+        REG_PC => machine.set_pc(word()),
+        REG_SP => return machine.set_sp(bytes[0]).is_ok(),
+        REG_DT => machine.set_dt(bytes[0]),
+        REG_ST => machine.set_st(bytes[0]),
+        _ => machine.set_vx(reg, bytes[0]),
+    }
+    true
+}
+
+/// Parses an `addr,length` pair, rejecting ranges that run past the end of
+/// memory
+fn parse_range(args: &str, mem_size: usize) -> Option<(usize, usize)> {
+    let mut parts = args.split(',');
+    let addr = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+    match addr.checked_add(len) {
+        Some(end) if end <= mem_size => Some((addr, len)),
+        _ => None,
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..(i + 2)).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
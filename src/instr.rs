@@ -1,1200 +1,1053 @@
 use std::fmt;
-use std::boxed::Box;
 
-use rand;
-use rand::Rng;
-
-use cpu::Cpu;
-use display::Pixel;
+use error::{Chip8Error, Result};
+use machine::Machine;
+use platform::Platform;
 use spec;
 
-pub trait Instr: fmt::Display {
-    fn parse(&mut self, instr: u16);
-    fn execute(&self, cpu: &mut Cpu);
-}
-
-/// *00E0 - CLS* :: Clear the display.
-#[derive(Default)]
-struct Cls {
-    raw: u16,
-}
-
-
-impl Instr for Cls {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        cpu.get_display().clear();
-    }
-}
-
-impl fmt::Display for Cls {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - CLS", self.raw)
-    }
-}
-
-
-/// *00EE - RET* :: Return from a subroutine.
-///
-/// The interpreter sets the program counter to the address at the top of the
-/// stack, then subtracts 1 from the stack pointer.
-#[derive(Default)]
-struct Ret {
-    raw: u16,
-}
-
-
-impl Instr for Ret {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let new_pc = cpu.pop_stack();
-        cpu.set_pc(new_pc);
-    }
-}
-
-impl fmt::Display for Ret {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - RET", self.raw)
-    }
-}
-
-/// *1nnn - JP addr* :: Jump to location nnn.
-///
-/// The interpreter sets the program counter to nnn.
-#[derive(Default)]
-struct Jp {
-    raw: u16,
-    addr: u16,
-}
-
-
-impl Instr for Jp {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.addr = instr & 0x0fff;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        cpu.set_pc(self.addr);
-    }
-}
-
-impl fmt::Display for Jp {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - JP {:03x}", self.raw, self.addr)
-    }
-}
-
-/// *2nnn - CALL addr* :: Call subroutine at nnn.
+/// A decoded instruction, with its operands.
 ///
-/// The interpreter increments the stack pointer, then puts the current PC on the
-/// top of the stack. The PC is then set to nnn.
-#[derive(Default)]
-struct Call {
-    raw: u16,
-    addr: u16,
-}
-
-impl Instr for Call {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.addr = instr & 0x0fff;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        // Store the current PC in the stack
-        let cur_pc = cpu.get_pc();
-        cpu.push_stack(cur_pc);
-
-        // Set the PC to the new address
-        cpu.set_pc(self.addr);
-    }
-}
-
-impl fmt::Display for Call {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - CALL {:03x}", self.raw, self.addr)
-    }
-}
-
-/// *3xkk - SE Vx, byte* :: Skip next instruction if Vx = kk.
-///
-/// The interpreter compares register Vx to kk, and if they are equal, increments
-/// the program counter by 2.
-#[derive(Default)]
-struct SeB {
-    raw: u16,
-    reg: usize,
-    value: u8,
-}
-
-impl Instr for SeB {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.reg = ((instr & 0x0f00) >> 8) as usize;
-        self.value = (instr & 0x00ff) as u8;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        if cpu.get_vx(self.reg) == self.value {
-            cpu.inc_pc();
+/// Operands follow the usual naming: `x` and `y` are register indexes, `n` a
+/// nibble, `kk` a byte and `nnn` an address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    /// *00E0 - CLS* :: Clear the display.
+    Cls,
+
+    /// *00Cn - SCD nibble* :: Scroll the display down by n lines. (SUPER-CHIP)
+    ScrollDown { n: u8 },
+
+    /// *00Dn - SCU nibble* :: Scroll the display up by n lines. (XO-CHIP)
+    ScrollUp { n: u8 },
+
+    /// *00EE - RET* :: Return from a subroutine.
+    ///
+    /// The interpreter sets the program counter to the address at the top of the
+    /// stack, then subtracts 1 from the stack pointer.
+    Ret,
+
+    /// *00FB - SCR* :: Scroll the display right by 4 pixels. (SUPER-CHIP)
+    ScrollRight,
+
+    /// *00FC - SCL* :: Scroll the display left by 4 pixels. (SUPER-CHIP)
+    ScrollLeft,
+
+    /// *00FD - EXIT* :: Exit the interpreter. (SUPER-CHIP)
+    Exit,
+
+    /// *00FE - LOW* :: Switch to the 64x32 low resolution mode. (SUPER-CHIP)
+    ///
+    /// The display is cleared.
+    LowRes,
+
+    /// *00FF - HIGH* :: Switch to the 128x64 high resolution mode. (SUPER-CHIP)
+    ///
+    /// The display is cleared.
+    HighRes,
+
+    /// *1nnn - JP addr* :: Jump to location nnn.
+    ///
+    /// The interpreter sets the program counter to nnn.
+    Jp { nnn: u16 },
+
+    /// *2nnn - CALL addr* :: Call subroutine at nnn.
+    ///
+    /// The interpreter increments the stack pointer, then puts the current PC on the
+    /// top of the stack. The PC is then set to nnn.
+    Call { nnn: u16 },
+
+    /// *3xkk - SE Vx, byte* :: Skip next instruction if Vx = kk.
+    ///
+    /// The interpreter compares register Vx to kk, and if they are equal, increments
+    /// the program counter by 2.
+    SeB { x: usize, kk: u8 },
+
+    /// *4xkk - SNE Vx, byte* :: Skip next instruction if Vx != kk.
+    ///
+    /// The interpreter compares register Vx to kk, and if they are not equal,
+    /// increments the program counter by 2.
+    Sne { x: usize, kk: u8 },
+
+    /// *5xy0 - SE Vx, Vy* :: Skip next instruction if Vx = Vy.
+    ///
+    /// The interpreter compares register Vx to register Vy, and if they are equal,
+    /// increments the program counter by 2.
+    SeV { x: usize, y: usize },
+
+    /// *5xy2 - LD [I], Vx - Vy* :: Store registers Vx through Vy in memory starting at location I. (XO-CHIP)
+    ///
+    /// Registers are stored in descending order if x > y. I is not changed.
+    SaveRange { x: usize, y: usize },
+
+    /// *5xy3 - LD Vx - Vy, [I]* :: Read registers Vx through Vy from memory starting at location I. (XO-CHIP)
+    ///
+    /// Registers are read in descending order if x > y. I is not changed.
+    LoadRange { x: usize, y: usize },
+
+    /// *6xkk - LD Vx, byte* :: Set Vx = kk.
+    ///
+    /// The interpreter puts the value kk into register Vx.
+    Ld { x: usize, kk: u8 },
+
+    /// *7xkk - ADD Vx, byte* :: Set Vx = Vx + kk.
+    ///
+    /// Adds the value kk to the value of register Vx, then stores the result in Vx.
+    AddB { x: usize, kk: u8 },
+
+    /// *8xy0 - LD Vx, Vy* :: Set Vx = Vy.
+    ///
+    /// Stores the value of register Vy in register Vx.
+    LdReg { x: usize, y: usize },
+
+    /// *8xy1 - OR Vx, Vy* :: Set Vx = Vx OR Vy.
+    ///
+    /// Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
+    /// A bitwise OR compares the corrseponding bits from two values, and if either bit
+    /// is 1, then the same bit in the result is also 1. Otherwise, it is 0.
+    /// VF is reset with `Quirks::vf_reset`.
+    Or { x: usize, y: usize },
+
+    /// *8xy2 - AND Vx, Vy* :: Set Vx = Vx AND Vy.
+    ///
+    /// Performs a bitwise AND on the values of Vx and Vy, then stores the result in
+    /// Vx. A bitwise AND compares the corrseponding bits from two values, and if both
+    /// bits are 1, then the same bit in the result is also 1. Otherwise, it is 0.
+    /// VF is reset with `Quirks::vf_reset`.
+    And { x: usize, y: usize },
+
+    /// *8xy3 - XOR Vx, Vy* :: Set Vx = Vx XOR Vy.
+    ///
+    /// Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the
+    /// result in Vx. An exclusive OR compares the corrseponding bits from two values,
+    /// and if the bits are not both the same, then the corresponding bit in the result
+    /// is set to 1. Otherwise, it is 0. VF is reset with `Quirks::vf_reset`.
+    Xor { x: usize, y: usize },
+
+    /// *8xy4 - ADD Vx, Vy* :: Set Vx = Vx + Vy, set VF = carry.
+    ///
+    /// The values of Vx and Vy are added together. If the result is greater than
+    /// 8 bits (i.e., > 255,) VF is set to 1, otherwise 0. Only the lowest 8 bits of
+    /// the result are kept, and stored in Vx.
+    AddV { x: usize, y: usize },
+
+    /// *8xy5 - SUB Vx, Vy* :: Set Vx = Vx - Vy, set VF = NOT borrow.
+    ///
+    /// Vy is subtracted from Vx, and the results stored in Vx. Then, if Vx >= Vy,
+    /// VF is set to 1, otherwise 0.
+    Sub { x: usize, y: usize },
+
+    /// *8xy6 - SHR Vx {, Vy}* :: Set Vx = Vx SHR 1.
+    ///
+    /// If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then
+    /// Vx is divided by 2. With `Quirks::shift_uses_vy`, Vy is shifted instead and
+    /// the result stored in Vx.
+    Shr { x: usize, y: usize },
+
+    /// *8xy7 - SUBN Vx, Vy* :: Set Vx = Vy - Vx, set VF = NOT borrow.
+    ///
+    /// Vx is subtracted from Vy, and the results stored in Vx. Then, if Vy >= Vx,
+    /// VF is set to 1, otherwise 0.
+    SubN { x: usize, y: usize },
+
+    /// *8xyE - SHL Vx {, Vy}* :: Set Vx = Vx SHL 1.
+    ///
+    /// If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0.
+    /// Then Vx is multiplied by 2. With `Quirks::shift_uses_vy`, Vy is shifted
+    /// instead and the result stored in Vx.
+    Shl { x: usize, y: usize },
+
+    /// *9xy0 - SNE Vx, Vy* :: Skip next instruction if Vx != Vy.
+    ///
+    /// The values of Vx and Vy are compared, and if they are not equal, the program
+    /// counter is increased by 2.
+    SneV { x: usize, y: usize },
+
+    /// *Annn - LD I, addr* :: Set I = nnn.
+    ///
+    /// The value of register I is set to nnn.
+    LdI { nnn: u16 },
+
+    /// *F000 nnnn - LD I, long addr* :: Set I = nnnn. (XO-CHIP)
+    ///
+    /// The 16 bit address is read from the two bytes following the instruction,
+    /// which are then skipped.
+    LdILong,
+
+    /// *Bnnn - JP V0, addr* :: Jump to location nnn + V0.
+    ///
+    /// The program counter is set to nnn plus the value of V0. With
+    /// `Quirks::jump_uses_vx`, Vx is used instead, x being the highest nibble of nnn.
+    JpV0 { nnn: u16 },
+
+    /// *Cxkk - RND Vx, byte* :: Set Vx = random byte AND kk.
+    ///
+    /// The interpreter generates a random number from 0 to 255, which is then ANDed
+    /// with the value kk. The results are stored in Vx. See instruction 8xy2 for more
+    /// information on AND. Random numbers come from the machine's seeded `Rng`.
+    Rnd { x: usize, kk: u8 },
+
+    /// *Dxyn - DRW Vx, Vy, nibble* :: Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+    ///
+    /// The interpreter reads n bytes from memory, starting at the address stored in I.
+    /// These bytes are then displayed as sprites on screen at coordinates (Vx, Vy).
+    /// Sprites are XORed onto the existing screen. If this causes any pixels to be
+    /// erased, VF is set to 1, otherwise it is set to 0. If the sprite is positioned
+    /// so part of it is outside the coordinates of the display, it wraps around to the
+    /// opposite side of the screen, or is clipped with `Quirks::clip_sprites`. See instruction 8xy3 for more information on XOR,
+    /// and section 2.4, Display, for more information on the Chip-8 screen and
+    /// sprites.
+    ///
+    /// SUPER-CHIP: with n = 0, a 16x16 sprite is drawn from the 32 bytes at I.
+    ///
+    /// XO-CHIP: the sprite is drawn on every selected plane, reading the data
+    /// for each plane in turn from I onwards.
+    Drw { x: usize, y: usize, n: u8 },
+
+    /// *Ex9E - SKP Vx* :: Skip next instruction if key with the value of Vx is pressed.
+    ///
+    /// Checks the keyboard, and if the key corresponding to the value of Vx is
+    /// currently in the down position, PC is increased by 2.
+    SkpVx { x: usize },
+
+    /// *ExA1 - SKNP Vx* :: Skip next instruction if key with the value of Vx is not pressed.
+    ///
+    /// Checks the keyboard, and if the key corresponding to the value of Vx is
+    /// currently in the up position, PC is increased by 2.
+    SknpVx { x: usize },
+
+    /// *Fn01 - PLANE n* :: Select the bitplanes n to draw on. (XO-CHIP)
+    Plane { n: u8 },
+
+    /// *F002 - AUDIO* :: Load the 16 byte audio pattern at I. (XO-CHIP)
+    ///
+    /// While ST is non-zero, the pattern is played as a stream of 1 bit samples
+    /// instead of the buzzer tone.
+    Audio,
+
+    /// *Fx07 - LD Vx, DT* :: Set Vx = delay timer value.
+    ///
+    /// The value of DT is placed into Vx.
+    LdVxDt { x: usize },
+
+    /// *Fx0A - LD Vx, K* :: Wait for a key press, store the value of the key in Vx.
+    ///
+    /// All execution stops until a key is pressed, then the value of that key is
+    /// stored in Vx.
+    LdVxK { x: usize },
+
+    /// *Fx15 - LD DT, Vx* :: Set delay timer = Vx.
+    ///
+    /// DT is set equal to the value of Vx.
+    LdDt { x: usize },
+
+    /// *Fx18 - LD ST, Vx* :: Set sound timer = Vx.
+    ///
+    /// ST is set equal to the value of Vx. The buzzer sounds for as long as ST
+    /// is non-zero.
+    LdSt { x: usize },
+
+    /// *Fx3A - PITCH Vx* :: Set the audio pattern playback rate = Vx. (XO-CHIP)
+    ///
+    /// The pattern plays at 4000 * 2^((Vx - 64) / 48) bits per second.
+    Pitch { x: usize },
+
+    /// *Fx1E - ADD I, Vx* :: Set I = I + Vx.
+    ///
+    /// The values of I and Vx are added, and the results are stored in I.
+    AddI { x: usize },
+
+    /// *Fx29 - LD F, Vx* :: Set I = location of sprite for digit Vx.
+    ///
+    /// The value of I is set to the location for the hexadecimal sprite corresponding
+    /// to the value of Vx. See section 2.4, Display, for more information on the
+    /// Chip-8 hexadecimal font.
+    LdSprite { x: usize },
+
+    /// *Fx33 - LD B, Vx* :: Store BCD representation of Vx in memory locations I, I+1, and I+2.
+    ///
+    /// The interpreter takes the decimal value of Vx, and places the hundreds digit in
+    /// memory at location in I, the tens digit at location I+1, and the ones digit at
+    /// location I+2.
+    LdBCD { x: usize },
+
+    /// *Fx30 - LD HF, Vx* :: Set I = location of 8x10 sprite for digit Vx. (SUPER-CHIP)
+    LdBigSprite { x: usize },
+
+    /// *Fx55 - LD [I], Vx* :: Store registers V0 through Vx in memory starting at location I.
+    ///
+    /// The interpreter copies the values of registers V0 through Vx into memory,
+    /// starting at the address in I. I is left past the last register with
+    /// `Quirks::load_store_increments_i`.
+    SaveRegs { x: usize },
+
+    /// *Fx65 - LD Vx, [I]* :: Read registers V0 through Vx from memory starting at location I.
+    ///
+    /// The interpreter reads values from memory starting at location I into registers
+    /// V0 through Vx. I is left past the last register with
+    /// `Quirks::load_store_increments_i`.
+    RestoreRegs { x: usize },
+
+    /// *Fx75 - LD R, Vx* :: Store V0 through Vx in the RPL user flags. (SUPER-CHIP)
+    SaveRpl { x: usize },
+
+    /// *Fx85 - LD Vx, R* :: Read V0 through Vx from the RPL user flags. (SUPER-CHIP)
+    LoadRpl { x: usize },
+}
+
+impl Opcode {
+    /// The instruction's form with its operands left out, e.g. `LD Vx, byte`
+    pub fn class(&self) -> &'static str {
+        match *self {
+            Opcode::Cls => "CLS",
+            Opcode::ScrollDown { .. } => "SCD nibble",
+            Opcode::ScrollUp { .. } => "SCU nibble",
+            Opcode::Ret => "RET",
+            Opcode::ScrollRight => "SCR",
+            Opcode::ScrollLeft => "SCL",
+            Opcode::Exit => "EXIT",
+            Opcode::LowRes => "LOW",
+            Opcode::HighRes => "HIGH",
+            Opcode::Jp { .. } => "JP addr",
+            Opcode::Call { .. } => "CALL addr",
+            Opcode::SeB { .. } => "SE Vx, byte",
+            Opcode::Sne { .. } => "SNE Vx, byte",
+            Opcode::SeV { .. } => "SE Vx, Vy",
+            Opcode::SaveRange { .. } => "LD [I], Vx - Vy",
+            Opcode::LoadRange { .. } => "LD Vx - Vy, [I]",
+            Opcode::Ld { .. } => "LD Vx, byte",
+            Opcode::AddB { .. } => "ADD Vx, byte",
+            Opcode::LdReg { .. } => "LD Vx, Vy",
+            Opcode::Or { .. } => "OR Vx, Vy",
+            Opcode::And { .. } => "AND Vx, Vy",
+            Opcode::Xor { .. } => "XOR Vx, Vy",
+            Opcode::AddV { .. } => "ADD Vx, Vy",
+            Opcode::Sub { .. } => "SUB Vx, Vy",
+            Opcode::Shr { .. } => "SHR Vx, Vy",
+            Opcode::SubN { .. } => "SUBN Vx, Vy",
+            Opcode::Shl { .. } => "SHL Vx, Vy",
+            Opcode::SneV { .. } => "SNE Vx, Vy",
+            Opcode::LdI { .. } => "LD I, addr",
+            Opcode::LdILong => "LD I, LONG",
+            Opcode::JpV0 { .. } => "JP V0, addr",
+            Opcode::Rnd { .. } => "RND Vx, byte",
+            Opcode::Drw { .. } => "DRW Vx, Vy, nibble",
+            Opcode::SkpVx { .. } => "SKP Vx",
+            Opcode::SknpVx { .. } => "SKNP Vx",
+            Opcode::Plane { .. } => "PLANE n",
+            Opcode::Audio => "AUDIO",
+            Opcode::LdVxDt { .. } => "LD Vx, DT",
+            Opcode::LdVxK { .. } => "LD Vx, K",
+            Opcode::LdDt { .. } => "LD DT, Vx",
+            Opcode::LdSt { .. } => "LD ST, Vx",
+            Opcode::AddI { .. } => "ADD I, Vx",
+            Opcode::LdSprite { .. } => "LD F, Vx",
+            Opcode::LdBigSprite { .. } => "LD HF, Vx",
+            Opcode::LdBCD { .. } => "LD B, Vx",
+            Opcode::Pitch { .. } => "PITCH Vx",
+            Opcode::SaveRegs { .. } => "LD [I], Vx",
+            Opcode::RestoreRegs { .. } => "LD Vx, [I]",
+            Opcode::SaveRpl { .. } => "LD R, Vx",
+            Opcode::LoadRpl { .. } => "LD Vx, R",
         }
     }
-}
 
-impl fmt::Display for SeB {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,
-               "{:04x} - SE V{:x}, {:02x}",
-               self.raw,
-               self.reg,
-               self.value)
-    }
-}
-
-/// *4xkk - SNE Vx, byte* :: Skip next instruction if Vx != kk.
-///
-/// The interpreter compares register Vx to kk, and if they are not equal,
-/// increments the program counter by 2.
-#[derive(Default)]
-struct Sne {
-    raw: u16,
-    reg: usize,
-    value: u8,
-}
-
-impl Instr for Sne {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.reg = ((instr & 0x0f00) >> 8) as usize;
-        self.value = (instr & 0x00ff) as u8;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        if cpu.get_vx(self.reg) != self.value {
-            cpu.inc_pc();
+    /// Whether the instruction only exists on XO-CHIP
+    pub fn is_xochip(&self) -> bool {
+        matches!(*self,
+                 Opcode::ScrollUp { .. } |
+                 Opcode::SaveRange { .. } |
+                 Opcode::LoadRange { .. } |
+                 Opcode::LdILong |
+                 Opcode::Plane { .. } |
+                 Opcode::Audio |
+                 Opcode::Pitch { .. })
+    }
+
+    /// Encode the instruction back into its raw form. Bits the decoder ignores
+    /// are left as 0, so `decode(raw)?.encode()` may differ from `raw`.
+    pub fn encode(&self) -> u16 {
+        let xy = |op: u16, x: usize, y: usize, n: u16| op | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xkk = |op: u16, x: usize, kk: u8| op | (x as u16) << 8 | kk as u16;
+
+        match *self {
+            Opcode::Cls => 0x00e0,
+            Opcode::ScrollDown { n } => 0x00c0 | n as u16,
+            Opcode::ScrollUp { n } => 0x00d0 | n as u16,
+            Opcode::Ret => 0x00ee,
+            Opcode::ScrollRight => 0x00fb,
+            Opcode::ScrollLeft => 0x00fc,
+            Opcode::Exit => 0x00fd,
+            Opcode::LowRes => 0x00fe,
+            Opcode::HighRes => 0x00ff,
+            Opcode::Jp { nnn } => 0x1000 | nnn,
+            Opcode::Call { nnn } => 0x2000 | nnn,
+            Opcode::SeB { x, kk } => xkk(0x3000, x, kk),
+            Opcode::Sne { x, kk } => xkk(0x4000, x, kk),
+            Opcode::SeV { x, y } => xy(0x5000, x, y, 0x0),
+            Opcode::SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            Opcode::LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            Opcode::Ld { x, kk } => xkk(0x6000, x, kk),
+            Opcode::AddB { x, kk } => xkk(0x7000, x, kk),
+            Opcode::LdReg { x, y } => xy(0x8000, x, y, 0x0),
+            Opcode::Or { x, y } => xy(0x8000, x, y, 0x1),
+            Opcode::And { x, y } => xy(0x8000, x, y, 0x2),
+            Opcode::Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Opcode::AddV { x, y } => xy(0x8000, x, y, 0x4),
+            Opcode::Sub { x, y } => xy(0x8000, x, y, 0x5),
+            Opcode::Shr { x, y } => xy(0x8000, x, y, 0x6),
+            Opcode::SubN { x, y } => xy(0x8000, x, y, 0x7),
+            Opcode::Shl { x, y } => xy(0x8000, x, y, 0xe),
+            Opcode::SneV { x, y } => xy(0x9000, x, y, 0x0),
+            Opcode::LdI { nnn } => 0xa000 | nnn,
+            Opcode::LdILong => 0xf000,
+            Opcode::JpV0 { nnn } => 0xb000 | nnn,
+            Opcode::Rnd { x, kk } => xkk(0xc000, x, kk),
+            Opcode::Drw { x, y, n } => xy(0xd000, x, y, n as u16),
+            Opcode::SkpVx { x } => xkk(0xe000, x, 0x9e),
+            Opcode::SknpVx { x } => xkk(0xe000, x, 0xa1),
+            Opcode::Plane { n } => xkk(0xf000, n as usize, 0x01),
+            Opcode::Audio => 0xf002,
+            Opcode::LdVxDt { x } => xkk(0xf000, x, 0x07),
+            Opcode::LdVxK { x } => xkk(0xf000, x, 0x0a),
+            Opcode::LdDt { x } => xkk(0xf000, x, 0x15),
+            Opcode::LdSt { x } => xkk(0xf000, x, 0x18),
+            Opcode::AddI { x } => xkk(0xf000, x, 0x1e),
+            Opcode::LdSprite { x } => xkk(0xf000, x, 0x29),
+            Opcode::LdBigSprite { x } => xkk(0xf000, x, 0x30),
+            Opcode::LdBCD { x } => xkk(0xf000, x, 0x33),
+            Opcode::Pitch { x } => xkk(0xf000, x, 0x3a),
+            Opcode::SaveRegs { x } => xkk(0xf000, x, 0x55),
+            Opcode::RestoreRegs { x } => xkk(0xf000, x, 0x65),
+            Opcode::SaveRpl { x } => xkk(0xf000, x, 0x75),
+            Opcode::LoadRpl { x } => xkk(0xf000, x, 0x85),
         }
     }
 }
 
-impl fmt::Display for Sne {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,
-               "{:04x} - SNE V{:x}, {:02x}",
-               self.raw,
-               self.reg,
-               self.value)
-    }
-}
-
-/// *5xy0 - SE Vx, Vy* :: Skip next instruction if Vx = Vy.
+/// Decode a raw instruction.
 ///
-/// The interpreter compares register Vx to register Vy, and if they are equal,
-/// increments the program counter by 2.
-#[derive(Default)]
-struct SeV {
-    raw: u16,
-    x: usize,
-    y: usize,
-}
-
-impl Instr for SeV {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-        self.y = ((instr & 0x00f0) >> 4) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        if cpu.get_vx(self.x) == cpu.get_vx(self.y) {
-            cpu.inc_pc();
+/// Unknown instructions are reported as `InvalidOpcode` at address 0; use
+/// `Chip8Error::at` to point the error at the instruction's real address.
+pub fn decode(raw: u16) -> Result<Opcode> {
+    let x = ((raw & 0x0f00) >> 8) as usize;
+    let y = ((raw & 0x00f0) >> 4) as usize;
+    let n = (raw & 0x000f) as u8;
+    let kk = (raw & 0x00ff) as u8;
+    let nnn = raw & 0x0fff;
+
+    let opcode = match raw & 0xf000 {
+        0x0000 => {
+            match raw & 0xfff0 {
+                0x00c0 => Opcode::ScrollDown { n },
+                0x00d0 => Opcode::ScrollUp { n },
+                _ => {
+                    match raw {
+                        0x00e0 => Opcode::Cls,
+                        0x00ee => Opcode::Ret,
+                        0x00fb => Opcode::ScrollRight,
+                        0x00fc => Opcode::ScrollLeft,
+                        0x00fd => Opcode::Exit,
+                        0x00fe => Opcode::LowRes,
+                        0x00ff => Opcode::HighRes,
+                        _ => return Err(Chip8Error::InvalidOpcode { addr: 0, raw }),
+                    }
+                }
+            }
         }
-    }
-}
-
-impl fmt::Display for SeV {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - SE V{:x}, V{:x}", self.raw, self.x, self.y)
-    }
-}
-
-/// *6xkk - LD Vx, byte* :: Set Vx = kk.
-///
-/// The interpreter puts the value kk into register Vx.
-#[derive(Default)]
-struct Ld {
-    raw: u16,
-    reg: usize,
-    value: u8,
-}
-
-impl Instr for Ld {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.reg = ((instr & 0x0f00) >> 8) as usize;
-        self.value = (instr & 0x00ff) as u8;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        cpu.set_vx(self.reg, self.value);
-    }
-}
-
-impl fmt::Display for Ld {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,
-               "{:04x} - LD V{:x}, {:02x}",
-               self.raw,
-               self.reg,
-               self.value)
-    }
-}
-
-/// *7xkk - ADD Vx, byte* :: Set Vx = Vx + kk.
-///
-/// Adds the value kk to the value of register Vx, then stores the result in Vx.
-#[derive(Default)]
-struct AddB {
-    raw: u16,
-    reg: usize,
-    value: u8,
-}
-
-impl Instr for AddB {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.reg = ((instr & 0x0f00) >> 8) as usize;
-        self.value = (instr & 0x00ff) as u8;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let new_value = cpu.get_vx(self.reg).wrapping_add(self.value);
-        cpu.set_vx(self.reg, new_value);
-    }
-}
-
-impl fmt::Display for AddB {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,
-               "{:04x} - ADD V{:x}, {:02x}",
-               self.raw,
-               self.reg,
-               self.value)
-    }
-}
-
-/// *8xy0 - LD Vx, Vy* :: Set Vx = Vy.
-///
-/// Stores the value of register Vy in register Vx.
-#[derive(Default)]
-struct LdReg {
-    raw: u16,
-    x: usize,
-    y: usize,
-}
-
-impl Instr for LdReg {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-        self.y = ((instr & 0x00f0) >> 4) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let new_value = cpu.get_vx(self.y);
-        cpu.set_vx(self.x, new_value);
-    }
-}
-
-impl fmt::Display for LdReg {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - LD V{:x}, V{:x}", self.raw, self.x, self.y)
-    }
-}
-
-/// *8xy1 - OR Vx, Vy* :: Set Vx = Vx OR Vy.
-///
-/// Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
-/// A bitwise OR compares the corrseponding bits from two values, and if either bit
-/// is 1, then the same bit in the result is also 1. Otherwise, it is 0.
-#[derive(Default)]
-struct Or {
-    raw: u16,
-    x: usize,
-    y: usize,
-}
-
-impl Instr for Or {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-        self.y = ((instr & 0x00f0) >> 4) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let new_value = cpu.get_vx(self.x) | cpu.get_vx(self.y);
-        cpu.set_vx(self.x, new_value);
-    }
-}
-
-impl fmt::Display for Or {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - OR V{:x}, V{:x}", self.raw, self.x, self.y)
-    }
-}
-
-/// *8xy2 - AND Vx, Vy* :: Set Vx = Vx AND Vy.
-///
-/// Performs a bitwise AND on the values of Vx and Vy, then stores the result in
-/// Vx. A bitwise AND compares the corrseponding bits from two values, and if both
-/// bits are 1, then the same bit in the result is also 1. Otherwise, it is 0.
-#[derive(Default)]
-struct And {
-    raw: u16,
-    x: usize,
-    y: usize,
-}
-
-impl Instr for And {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-        self.y = ((instr & 0x00f0) >> 4) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let new_value = cpu.get_vx(self.x) & cpu.get_vx(self.y);
-        cpu.set_vx(self.x, new_value);
-    }
-}
-
-impl fmt::Display for And {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - AND V{:x}, V{:x}", self.raw, self.x, self.y)
-    }
-}
-
-/// *8xy3 - XOR Vx, Vy* :: Set Vx = Vx XOR Vy.
-///
-/// Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the
-/// result in Vx. An exclusive OR compares the corrseponding bits from two values,
-/// and if the bits are not both the same, then the corresponding bit in the result
-/// is set to 1. Otherwise, it is 0.
-#[derive(Default)]
-struct Xor {
-    raw: u16,
-    x: usize,
-    y: usize,
-}
-
-impl Instr for Xor {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-        self.y = ((instr & 0x00f0) >> 4) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let new_value = cpu.get_vx(self.x) ^ cpu.get_vx(self.y);
-        cpu.set_vx(self.x, new_value);
-    }
-}
-
-impl fmt::Display for Xor {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - XOR V{:x}, V{:x}", self.raw, self.x, self.y)
-    }
-}
-
-/// *8xy4 - ADD Vx, Vy* :: Set Vx = Vx + Vy, set VF = carry.
-///
-/// The values of Vx and Vy are added together. If the result is greater than
-/// 8 bits (i.e., > 255,) VF is set to 1, otherwise 0. Only the lowest 8 bits of
-/// the result are kept, and stored in Vx.
-#[derive(Default)]
-struct AddV {
-    raw: u16,
-    x: usize,
-    y: usize,
-}
-
-impl Instr for AddV {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-        self.y = ((instr & 0x00f0) >> 4) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let (new_value, overflow) = cpu.get_vx(self.x).overflowing_add(cpu.get_vx(self.y));
-
-        cpu.set_vx(self.x, new_value);
-
-        if overflow {
-            cpu.set_vx(0xf, 1);
-        } else {
-            cpu.set_vx(0xf, 0);
+        0x1000 => Opcode::Jp { nnn },
+        0x2000 => Opcode::Call { nnn },
+        0x3000 => Opcode::SeB { x, kk },
+        0x4000 => Opcode::Sne { x, kk },
+        0x5000 => {
+            match raw & 0x000f {
+                0x0000 => Opcode::SeV { x, y },
+                0x0002 => Opcode::SaveRange { x, y },
+                0x0003 => Opcode::LoadRange { x, y },
+                _ => return Err(Chip8Error::InvalidOpcode { addr: 0, raw }),
+            }
         }
-
-    }
-}
-
-impl fmt::Display for AddV {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - ADD V{:x}, V{:x}", self.raw, self.x, self.y)
-    }
-}
-
-/// *8xy5 - SUB Vx, Vy* :: Set Vx = Vx - Vy, set VF = NOT borrow.
-///
-/// If Vx > Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx,
-/// and the results stored in Vx.
-#[derive(Default)]
-struct Sub {
-    raw: u16,
-    x: usize,
-    y: usize,
-}
-
-impl Instr for Sub {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-        self.y = ((instr & 0x00f0) >> 4) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let vx = cpu.get_vx(self.x);
-        let vy = cpu.get_vx(self.y);
-
-        if vx > vy {
-            cpu.set_vx(0xf, 1);
-        } else {
-            cpu.set_vx(0xf, 0);
+        0x6000 => Opcode::Ld { x, kk },
+        0x7000 => Opcode::AddB { x, kk },
+        0x8000 => {
+            match raw & 0x000f {
+                0x0000 => Opcode::LdReg { x, y },
+                0x0001 => Opcode::Or { x, y },
+                0x0002 => Opcode::And { x, y },
+                0x0003 => Opcode::Xor { x, y },
+                0x0004 => Opcode::AddV { x, y },
+                0x0005 => Opcode::Sub { x, y },
+                0x0006 => Opcode::Shr { x, y },
+                0x0007 => Opcode::SubN { x, y },
+                0x000e => Opcode::Shl { x, y },
+                _ => return Err(Chip8Error::InvalidOpcode { addr: 0, raw }),
+            }
         }
-
-        let new_value = vx.wrapping_sub(vy);
-        cpu.set_vx(self.x, new_value);
-    }
-}
-
-impl fmt::Display for Sub {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - SUB V{:x}, V{:x}", self.raw, self.x, self.y)
-    }
-}
-
-/// *8xy6 - SHR Vx {, Vy}* :: Set Vx = Vx SHR 1.
-///
-/// If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then
-/// Vx is divided by 2.
-#[derive(Default)]
-struct Shr {
-    raw: u16,
-    x: usize,
-}
-
-impl Instr for Shr {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let vx = cpu.get_vx(self.x);
-
-        if vx & 0x01 == 0x01 {
-            cpu.set_vx(0xf, 1);
-        } else {
-            cpu.set_vx(0xf, 0);
+        0x9000 => Opcode::SneV { x, y },
+        0xa000 => Opcode::LdI { nnn },
+        0xb000 => Opcode::JpV0 { nnn },
+        0xc000 => Opcode::Rnd { x, kk },
+        0xd000 => Opcode::Drw { x, y, n },
+        0xe000 => {
+            match raw & 0x00ff {
+                0x009e => Opcode::SkpVx { x },
+                0x00a1 => Opcode::SknpVx { x },
+                _ => return Err(Chip8Error::InvalidOpcode { addr: 0, raw }),
+            }
         }
-
-        cpu.set_vx(self.x, vx >> 1);
-    }
-}
-
-impl fmt::Display for Shr {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - SHR V{:x}", self.raw, self.x)
-    }
-}
-
-/// *8xy7 - SUBN Vx, Vy* :: Set Vx = Vy - Vx, set VF = NOT borrow.
-///
-/// If Vy > Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy,
-/// and the results stored in Vx.
-#[derive(Default)]
-struct SubN {
-    raw: u16,
-    x: usize,
-    y: usize,
-}
-
-impl Instr for SubN {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-        self.y = ((instr & 0x00f0) >> 4) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let vx = cpu.get_vx(self.x);
-        let vy = cpu.get_vx(self.y);
-
-        if vy > vx {
-            cpu.set_vx(0xf, 1);
-        } else {
-            cpu.set_vx(0xf, 0);
+        0xf000 => {
+            match raw & 0x00ff {
+                0x0000 if x == 0 => Opcode::LdILong,
+                0x0001 => Opcode::Plane { n: x as u8 },
+                0x0002 if x == 0 => Opcode::Audio,
+                0x0007 => Opcode::LdVxDt { x },
+                0x000a => Opcode::LdVxK { x },
+                0x0015 => Opcode::LdDt { x },
+                0x0018 => Opcode::LdSt { x },
+                0x001e => Opcode::AddI { x },
+                0x0029 => Opcode::LdSprite { x },
+                0x0030 => Opcode::LdBigSprite { x },
+                0x0033 => Opcode::LdBCD { x },
+                0x003a => Opcode::Pitch { x },
+                0x0055 => Opcode::SaveRegs { x },
+                0x0065 => Opcode::RestoreRegs { x },
+                0x0075 => Opcode::SaveRpl { x },
+                0x0085 => Opcode::LoadRpl { x },
+                _ => return Err(Chip8Error::InvalidOpcode { addr: 0, raw }),
+            }
         }
+        _ => return Err(Chip8Error::InvalidOpcode { addr: 0, raw }),
+    };
 
-        let new_value = vy.wrapping_sub(vx);
-        cpu.set_vx(self.x, new_value);
-    }
-}
-
-impl fmt::Display for SubN {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - SUBN V{:x}, V{:x}", self.raw, self.x, self.y)
-    }
-}
-
-/// *8xyE - SHL Vx {, Vy}* :: Set Vx = Vx SHL 1.
-///
-/// If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0.
-/// Then Vx is multiplied by 2.
-#[derive(Default)]
-struct Shl {
-    raw: u16,
-    x: usize,
+    Ok(opcode)
 }
 
-impl Instr for Shl {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
+/// Execute a decoded instruction on the machine. XO-CHIP instructions are
+/// invalid on the other platforms.
+pub fn execute(opcode: Opcode, machine: &mut Machine) -> Result<()> {
+    if opcode.is_xochip() && machine.platform() != Platform::XoChip {
+        return Err(Chip8Error::InvalidOpcode { addr: 0, raw: opcode.encode() });
     }
 
-    fn execute(&self, cpu: &mut Cpu) {
-        let vx = cpu.get_vx(self.x);
-
-        if vx & 0x01 == 0x01 {
-            cpu.set_vx(0xf, 1);
-        } else {
-            cpu.set_vx(0xf, 0);
+    match opcode {
+        Opcode::Cls => {
+            machine.get_framebuffer().clear();
         }
+        Opcode::ScrollDown { n } => {
+            machine.get_framebuffer().scroll_down(n as usize);
+        }
+        Opcode::ScrollUp { n } => {
+            machine.get_framebuffer().scroll_up(n as usize);
+        }
+        Opcode::Ret => {
+            let new_pc = machine.pop_stack()?;
+            machine.set_pc(new_pc);
+        }
+        Opcode::ScrollRight => {
+            machine.get_framebuffer().scroll_right(4);
+        }
+        Opcode::ScrollLeft => {
+            machine.get_framebuffer().scroll_left(4);
+        }
+        Opcode::Exit => {
+            machine.halt();
+        }
+        Opcode::LowRes => {
+            machine.get_framebuffer().set_hires(false);
+        }
+        Opcode::HighRes => {
+            machine.get_framebuffer().set_hires(true);
+        }
+        Opcode::Jp { nnn } => {
+            machine.set_pc(nnn);
+        }
+        Opcode::Call { nnn } => {
+            // Store the current PC in the stack
+            let cur_pc = machine.get_pc();
+            machine.push_stack(cur_pc)?;
 
-        cpu.set_vx(self.x, vx << 1);
-    }
-}
-
-impl fmt::Display for Shl {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - SHL V{:x}", self.raw, self.x)
-    }
-}
-
-/// *9xy0 - SNE Vx, Vy* :: Skip next instruction if Vx != Vy.
-///
-/// The values of Vx and Vy are compared, and if they are not equal, the program
-/// counter is increased by 2.
-#[derive(Default)]
-struct SneV {
-    raw: u16,
-    x: usize,
-    y: usize,
-}
-
-impl Instr for SneV {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-        self.y = ((instr & 0x00f0) >> 4) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        if cpu.get_vx(self.x) != cpu.get_vx(self.y) {
-            cpu.inc_pc();
+            // Set the PC to the new address
+            machine.set_pc(nnn);
         }
-    }
-}
+        Opcode::SeB { x, kk } => {
+            if machine.get_vx(x) == kk {
+                machine.skip_next();
+            }
+        }
+        Opcode::Sne { x, kk } => {
+            if machine.get_vx(x) != kk {
+                machine.skip_next();
+            }
+        }
+        Opcode::SeV { x, y } => {
+            if machine.get_vx(x) == machine.get_vx(y) {
+                machine.skip_next();
+            }
+        }
+        Opcode::SaveRange { x, y } => {
+            for (offset, reg) in register_range(x, y).enumerate() {
+                let addr = machine.get_i() as usize + offset;
+                let value = machine.get_vx(reg);
+                machine.put_mem(addr, value)?;
+            }
+        }
+        Opcode::LoadRange { x, y } => {
+            for (offset, reg) in register_range(x, y).enumerate() {
+                let addr = machine.get_i() as usize + offset;
+                let value = machine.read_mem(addr, 1)?[0];
+                machine.set_vx(reg, value);
+            }
+        }
+        Opcode::Ld { x, kk } => {
+            machine.set_vx(x, kk);
+        }
+        Opcode::AddB { x, kk } => {
+            let new_value = machine.get_vx(x).wrapping_add(kk);
+            machine.set_vx(x, new_value);
+        }
+        Opcode::LdReg { x, y } => {
+            let new_value = machine.get_vx(y);
+            machine.set_vx(x, new_value);
+        }
+        Opcode::Or { x, y } => {
+            let new_value = machine.get_vx(x) | machine.get_vx(y);
+            machine.set_vx(x, new_value);
+            reset_vf(machine);
+        }
+        Opcode::And { x, y } => {
+            let new_value = machine.get_vx(x) & machine.get_vx(y);
+            machine.set_vx(x, new_value);
+            reset_vf(machine);
+        }
+        Opcode::Xor { x, y } => {
+            let new_value = machine.get_vx(x) ^ machine.get_vx(y);
+            machine.set_vx(x, new_value);
+            reset_vf(machine);
+        }
+        Opcode::AddV { x, y } => {
+            let (new_value, overflow) = machine.get_vx(x).overflowing_add(machine.get_vx(y));
 
-impl fmt::Display for SneV {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - SNE V{:x}, V{:x}", self.raw, self.x, self.y)
-    }
-}
+            machine.set_vx(x, new_value);
+            machine.set_vx(0xf, overflow as u8);
+        }
+        Opcode::Sub { x, y } => {
+            let vx = machine.get_vx(x);
+            let vy = machine.get_vx(y);
 
-/// *Annn - LD I, addr* :: Set I = nnn.
-///
-/// The value of register I is set to nnn.
-#[derive(Default)]
-struct LdI {
-    raw: u16,
-    addr: u16,
-}
+            // VF last, so that the flag wins when x is F
+            machine.set_vx(x, vx.wrapping_sub(vy));
+            machine.set_vx(0xf, (vx >= vy) as u8);
+        }
+        Opcode::Shr { x, y } => {
+            let value = shift_operand(machine, x, y);
 
-impl Instr for LdI {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.addr = instr & 0x0fff;
-    }
+            machine.set_vx(x, value >> 1);
+            machine.set_vx(0xf, value & 0x01);
+        }
+        Opcode::SubN { x, y } => {
+            let vx = machine.get_vx(x);
+            let vy = machine.get_vx(y);
 
-    fn execute(&self, cpu: &mut Cpu) {
-        cpu.set_i(self.addr);
-    }
-}
+            machine.set_vx(x, vy.wrapping_sub(vx));
+            machine.set_vx(0xf, (vy >= vx) as u8);
+        }
+        Opcode::Shl { x, y } => {
+            let value = shift_operand(machine, x, y);
 
-impl fmt::Display for LdI {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - LD I, {:03x}", self.raw, self.addr)
+            machine.set_vx(x, value << 1);
+            machine.set_vx(0xf, value >> 7);
+        }
+        Opcode::SneV { x, y } => {
+            if machine.get_vx(x) != machine.get_vx(y) {
+                machine.skip_next();
+            }
+        }
+        Opcode::LdI { nnn } => {
+            machine.set_i(nnn);
+        }
+        Opcode::LdILong => {
+            let addr = machine.read_instr()?;
+            machine.set_i(addr);
+        }
+        Opcode::JpV0 { nnn } => {
+            let reg = if machine.quirks().jump_uses_vx {
+                (nnn >> 8) as usize
+            } else {
+                0
+            };
+            let incr = machine.get_vx(reg) as u16;
+            machine.set_pc(nnn + incr);
+        }
+        Opcode::Rnd { x, kk } => {
+            let rnd_byte = machine.random_byte();
+            machine.set_vx(x, rnd_byte & kk);
+        }
+        Opcode::Drw { x, y, n } => {
+            draw(machine, x, y, n)?;
+        }
+        Opcode::SkpVx { x } => {
+            // Only the low nibble names a key, as on the VIP
+            let value = machine.get_vx(x) as usize & 0xf;
+            if machine.pressed(value) {
+                machine.skip_next();
+            }
+        }
+        Opcode::SknpVx { x } => {
+            // Only the low nibble names a key, as on the VIP
+            let value = machine.get_vx(x) as usize & 0xf;
+            if !machine.pressed(value) {
+                machine.skip_next();
+            }
+        }
+        Opcode::Plane { n } => {
+            machine.get_framebuffer().set_planes(n);
+        }
+        Opcode::Audio => {
+            let bytes = machine.read_mem(machine.get_i() as usize, spec::AUDIO_PATTERN_SIZE)?;
+            let mut pattern = [0u8; spec::AUDIO_PATTERN_SIZE];
+            pattern.copy_from_slice(&bytes);
+            machine.set_audio_pattern(pattern);
+        }
+        Opcode::Pitch { x } => {
+            let value = machine.get_vx(x);
+            machine.set_pitch(value);
+        }
+        Opcode::LdVxDt { x } => {
+            let value = machine.get_dt();
+            machine.set_vx(x, value);
+        }
+        Opcode::LdVxK { x } => {
+            machine.wait_for_input(x);
+        }
+        Opcode::LdDt { x } => {
+            let value = machine.get_vx(x);
+            machine.set_dt(value);
+        }
+        Opcode::LdSt { x } => {
+            let value = machine.get_vx(x);
+            machine.set_st(value);
+        }
+        Opcode::AddI { x } => {
+            let result = machine.get_i().wrapping_add(machine.get_vx(x) as u16);
+            machine.set_i(result);
+        }
+        Opcode::LdSprite { x } => {
+            let value = machine.get_vx(x) as u16 & 0x0f;
+            machine.set_i(spec::FONT_START as u16 + value * 5);
+        }
+        Opcode::LdBigSprite { x } => {
+            let value = machine.get_vx(x) as u16 & 0x0f;
+            machine.set_i(spec::BIG_FONT_START as u16 + value * 10);
+        }
+        Opcode::LdBCD { x } => {
+            let mut value = machine.get_vx(x);
+            let mem_idx = machine.get_i() as usize;
+            machine.set_mem(mem_idx + 2, value % 10)?;
+            value /= 10;
+            machine.set_mem(mem_idx + 1, value % 10)?;
+            value /= 10;
+            machine.set_mem(mem_idx, value % 10)?;
+        }
+        Opcode::SaveRegs { x } => {
+            for i in 0..=x {
+                let addr = machine.get_i() as usize + i;
+                let value = machine.get_vx(i);
+                machine.put_mem(addr, value)?;
+            }
+            increment_i(machine, x);
+        }
+        Opcode::RestoreRegs { x } => {
+            for i in 0..=x {
+                let addr = machine.get_i() as usize + i;
+                let value = machine.read_mem(addr, 1)?[0];
+                machine.set_vx(i, value)
+            }
+            increment_i(machine, x);
+        }
+        Opcode::SaveRpl { x } => {
+            for i in 0..=x {
+                let value = machine.get_vx(i);
+                machine.set_rpl(i, value);
+            }
+        }
+        Opcode::LoadRpl { x } => {
+            for i in 0..=x {
+                let value = machine.get_rpl(i);
+                machine.set_vx(i, value);
+            }
+        }
     }
-}
 
-/// *Bnnn - JP V0, addr* :: Jump to location nnn + V0.
-///
-/// The program counter is set to nnn plus the value of V0.
-#[derive(Default)]
-struct JpV0 {
-    raw: u16,
-    addr: u16,
+    Ok(())
 }
 
-impl Instr for JpV0 {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.addr = instr & 0x0fff;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let incr = cpu.get_vx(0) as u16;
-        cpu.set_pc(self.addr + incr);
+/// `Quirks::vf_reset`: the logic instructions clobber VF
+fn reset_vf(machine: &mut Machine) {
+    if machine.quirks().vf_reset {
+        machine.set_vx(0xf, 0);
     }
 }
 
-impl fmt::Display for JpV0 {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - JP V0, {:03x}", self.raw, self.addr)
+/// `Quirks::shift_uses_vy`: the value to be shifted into Vx
+fn shift_operand(machine: &Machine, x: usize, y: usize) -> u8 {
+    if machine.quirks().shift_uses_vy {
+        machine.get_vx(y)
+    } else {
+        machine.get_vx(x)
     }
 }
 
-/// *Cxkk - RND Vx, byte* :: Set Vx = random byte AND kk.
-///
-/// The interpreter generates a random number from 0 to 255, which is then ANDed
-/// with the value kk. The results are stored in Vx. See instruction 8xy2 for more
-/// information on AND.
-#[derive(Default)]
-struct Rnd {
-    raw: u16,
-    reg: usize,
-    value: u8,
-}
-
-impl Instr for Rnd {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.reg = ((instr & 0x0f00) >> 8) as usize;
-        self.value = (instr & 0x00ff) as u8;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let rnd_byte = rand::thread_rng().gen::<u8>();
-        cpu.set_vx(self.reg, rnd_byte & self.value);
+/// `Quirks::load_store_increments_i`: leave I past the last register
+/// transferred by `Fx55`/`Fx65`
+fn increment_i(machine: &mut Machine, x: usize) {
+    if machine.quirks().load_store_increments_i {
+        let value = machine.get_i().wrapping_add(x as u16 + 1);
+        machine.set_i(value);
     }
 }
 
-impl fmt::Display for Rnd {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,
-               "{:04x} - RND V{:x}, {:02x}",
-               self.raw,
-               self.reg,
-               self.value)
+/// Registers x through y, counting down if x > y
+fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
     }
 }
 
-/// *Dxyn - DRW Vx, Vy, nibble* :: Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+/// Draws the n-byte sprite at I on (Vx, Vy). With n = 0, draws the 16x16
+/// SUPER-CHIP sprite at I instead, stored as 16 rows of 2 bytes.
 ///
-/// The interpreter reads n bytes from memory, starting at the address stored in I.
-/// These bytes are then displayed as sprites on screen at coordinates (Vx, Vy).
-/// Sprites are XORed onto the existing screen. If this causes any pixels to be
-/// erased, VF is set to 1, otherwise it is set to 0. If the sprite is positioned
-/// so part of it is outside the coordinates of the display, it wraps around to the
-/// opposite side of the screen. See instruction 8xy3 for more information on XOR,
-/// and section 2.4, Display, for more information on the Chip-8 screen and
-/// sprites.
-#[derive(Default)]
-struct Drw {
-    raw: u16,
-    x: usize,
-    y: usize,
-    n: u8,
-}
-
-impl Instr for Drw {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-        self.y = ((instr & 0x00f0) >> 4) as usize;
-        self.n = (instr & 0x000f) as u8;
-    }
-
-    #[allow(unused_variables)]
-    fn execute(&self, cpu: &mut Cpu) {
-        let x = cpu.get_vx(self.x);
-        let y = cpu.get_vx(self.y);
-        let i = cpu.get_i();
-        let n = self.n;
-
-        // Set VF as 0 by default.
-        let mut vf = 0;
-
-        // Read data to be drawn
-        let raw_bytes = cpu.read_mem(i as usize, n as usize);
-
-        let mut pixels: Vec<Pixel> = vec![];
-        for (iter_y, byte) in raw_bytes.iter().enumerate() {
+/// The starting coordinates always wrap around the screen; whether the rest of
+/// the sprite wraps too or is clipped depends on `Quirks::clip_sprites`.
+fn draw(machine: &mut Machine, x: usize, y: usize, n: u8) -> Result<()> {
+    let quirks = machine.quirks();
+    let planes = machine.framebuffer().planes();
+    let width = machine.framebuffer().width();
+    let height = machine.framebuffer().height();
+    let x = machine.get_vx(x) as usize % width;
+    let y = machine.get_vx(y) as usize % height;
+    let mut addr = machine.get_i() as usize;
+
+    // Sprite size in pixels
+    let (sprite_width, sprite_height) = if n == 0 { (16, 16) } else { (8, n as usize) };
+    let row_bytes = sprite_width / 8;
+    let sprite_bytes = sprite_height * row_bytes;
+
+    // Set VF as 0 by default.
+    let mut vf = 0;
+
+    for plane in (0..4).map(|bit| 1u8 << bit).filter(|plane| planes & plane != 0) {
+        // Read data to be drawn on this plane
+        let raw_bytes = machine.read_mem(addr, sprite_bytes)?;
+        addr += sprite_bytes;
+
+        for (iter_y, row) in raw_bytes.chunks(row_bytes).enumerate() {
             // Get the wrapped y coord
-            let dy = (y as u32 + iter_y as u32) % spec::DISPLAY_HEIGHT;
-            for iter_x in 0..8 {
-                // Get the wrapped x coord
-                let dx = (x as u32 + iter_x as u32) % spec::DISPLAY_WIDTH;
+            let dy = y + iter_y;
+            if quirks.clip_sprites && dy >= height {
+                break;
+            }
+            let dy = dy % height;
 
-                // Get the new and old bit value for the current pixel
-                let px = byte >> (7 - iter_x) & 0x01u8;
-                let old_px = cpu.get_display().get_pixel(dx as usize, dy as usize);
+            for iter_x in 0..sprite_width {
+                // Get the wrapped x coord
+                let dx = x + iter_x;
+                if quirks.clip_sprites && dx >= width {
+                    break;
+                }
+                let dx = dx % width;
 
-                // Calculate the new pixel value
+                // Flip the pixel if it is set in the sprite,
                 // and store any collision in VF
-                let new_px = old_px ^ px;
-                if old_px == 1 && new_px == 0 {
-                    vf = 1
+                let byte = row[iter_x / 8];
+                if byte >> (7 - iter_x % 8) & 0x01u8 == 1 &&
+                   machine.get_framebuffer().flip(dx, dy, plane) {
+                    vf = 1;
                 }
-
-                // Push the pixel to the pixels to be drawn
-                let pixel = Pixel::new(dx as usize, dy as usize, new_px);
-                pixels.push(pixel);
             }
         }
-
-        cpu.set_vx(0xf, vf);
-        cpu.get_display().draw(pixels);
     }
-}
 
-impl fmt::Display for Drw {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,
-               "{:04x} - DRW V{:x}, V{:x} {:x}",
-               self.raw,
-               self.x,
-               self.y,
-               self.n)
-    }
-}
-
-/// *Ex9E - SKP Vx* :: Skip next instruction if key with the value of Vx is pressed.
-///
-/// Checks the keyboard, and if the key corresponding to the value of Vx is
-/// currently in the down position, PC is increased by 2.
-#[derive(Default)]
-struct SkpVx {
-    raw: u16,
-    reg: usize,
-}
+    machine.set_vx(0xf, vf);
 
-impl Instr for SkpVx {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.reg = ((instr & 0x0f00) >> 8) as usize;
+    if quirks.display_wait {
+        machine.wait_vblank();
     }
 
-    fn execute(&self, cpu: &mut Cpu) {
-        let value = cpu.get_vx(self.reg) as usize;
-        if cpu.get_keyboard().pressed(value) {
-            cpu.inc_pc();
-        }
-    }
+    Ok(())
 }
 
-impl fmt::Display for SkpVx {
+/// The mnemonic, e.g. `LD V1, 05`; the alternate form `{:#}` puts the raw
+/// word in front of it, as in `6105 - LD V1, 05`
+impl fmt::Display for Opcode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - SKP V{:x}", self.raw, self.reg)
-    }
-}
-
-/// *ExA1 - SKNP Vx* :: Skip next instruction if key with the value of Vx is not pressed.
-///
-/// Checks the keyboard, and if the key corresponding to the value of Vx is
-/// currently in the up position, PC is increased by 2.
-#[derive(Default)]
-struct SknpVx {
-    raw: u16,
-    reg: usize,
-}
-
-impl Instr for SknpVx {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.reg = ((instr & 0x0f00) >> 8) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let value = cpu.get_vx(self.reg) as usize;
-        if !cpu.get_keyboard().pressed(value) {
-            cpu.inc_pc();
+        if f.alternate() {
+            write!(f, "{:04x} - ", self.encode())?;
+        }
+        match *self {
+            Opcode::Cls => write!(f, "CLS"),
+            Opcode::ScrollDown { n } => write!(f, "SCD {:x}", n),
+            Opcode::ScrollUp { n } => write!(f, "SCU {:x}", n),
+            Opcode::Ret => write!(f, "RET"),
+            Opcode::ScrollRight => write!(f, "SCR"),
+            Opcode::ScrollLeft => write!(f, "SCL"),
+            Opcode::Exit => write!(f, "EXIT"),
+            Opcode::LowRes => write!(f, "LOW"),
+            Opcode::HighRes => write!(f, "HIGH"),
+            Opcode::Jp { nnn } => write!(f, "JP {:03x}", nnn),
+            Opcode::Call { nnn } => write!(f, "CALL {:03x}", nnn),
+            Opcode::SeB { x, kk } => write!(f, "SE V{:x}, {:02x}", x, kk),
+            Opcode::Sne { x, kk } => write!(f, "SNE V{:x}, {:02x}", x, kk),
+            Opcode::SeV { x, y } => write!(f, "SE V{:x}, V{:x}", x, y),
+            Opcode::SaveRange { x, y } => write!(f, "LD [I], V{:x} - V{:x}", x, y),
+            Opcode::LoadRange { x, y } => write!(f, "LD V{:x} - V{:x}, [I]", x, y),
+            Opcode::Ld { x, kk } => write!(f, "LD V{:x}, {:02x}", x, kk),
+            Opcode::AddB { x, kk } => write!(f, "ADD V{:x}, {:02x}", x, kk),
+            Opcode::LdReg { x, y } => write!(f, "LD V{:x}, V{:x}", x, y),
+            Opcode::Or { x, y } => write!(f, "OR V{:x}, V{:x}", x, y),
+            Opcode::And { x, y } => write!(f, "AND V{:x}, V{:x}", x, y),
+            Opcode::Xor { x, y } => write!(f, "XOR V{:x}, V{:x}", x, y),
+            Opcode::AddV { x, y } => write!(f, "ADD V{:x}, V{:x}", x, y),
+            Opcode::Sub { x, y } => write!(f, "SUB V{:x}, V{:x}", x, y),
+            Opcode::Shr { x, y } => write!(f, "SHR V{:x}, V{:x}", x, y),
+            Opcode::SubN { x, y } => write!(f, "SUBN V{:x}, V{:x}", x, y),
+            Opcode::Shl { x, y } => write!(f, "SHL V{:x}, V{:x}", x, y),
+            Opcode::SneV { x, y } => write!(f, "SNE V{:x}, V{:x}", x, y),
+            Opcode::LdI { nnn } => write!(f, "LD I, {:03x}", nnn),
+            Opcode::LdILong => write!(f, "LD I, LONG"),
+            Opcode::JpV0 { nnn } => write!(f, "JP V0, {:03x}", nnn),
+            Opcode::Rnd { x, kk } => write!(f, "RND V{:x}, {:02x}", x, kk),
+            Opcode::Drw { x, y, n } => write!(f, "DRW V{:x}, V{:x} {:x}", x, y, n),
+            Opcode::Plane { n } => write!(f, "PLANE {:x}", n),
+            Opcode::Audio => write!(f, "AUDIO"),
+            Opcode::Pitch { x } => write!(f, "PITCH V{:x}", x),
+            Opcode::SkpVx { x } => write!(f, "SKP V{:x}", x),
+            Opcode::SknpVx { x } => write!(f, "SKNP V{:x}", x),
+            Opcode::LdVxDt { x } => write!(f, "LD V{:x}, DT", x),
+            Opcode::LdVxK { x } => write!(f, "LD V{:x}, K", x),
+            Opcode::LdDt { x } => write!(f, "LD DT, V{:x}", x),
+            Opcode::LdSt { x } => write!(f, "LD ST, V{:x}", x),
+            Opcode::AddI { x } => write!(f, "ADD I, V{:x}", x),
+            Opcode::LdSprite { x } => write!(f, "LD F, V{:x}", x),
+            Opcode::LdBigSprite { x } => write!(f, "LD HF, V{:x}", x),
+            Opcode::LdBCD { x } => write!(f, "LD B, V{:x}", x),
+            Opcode::SaveRegs { x } => write!(f, "LD [I], V{:x}", x),
+            Opcode::RestoreRegs { x } => write!(f, "LD V{:x}, [I]", x),
+            Opcode::SaveRpl { x } => write!(f, "LD R, V{:x}", x),
+            Opcode::LoadRpl { x } => write!(f, "LD V{:x}, R", x),
         }
     }
 }
 
-impl fmt::Display for SknpVx {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - SKNP V{:x}", self.raw, self.reg)
-    }
-}
-
-
-/// *Fx07 - LD Vx, DT* :: Set Vx = delay timer value.
-///
-/// The value of DT is placed into Vx.
-#[derive(Default)]
-struct LdVxDt {
-    raw: u16,
-    reg: usize,
-}
-
-impl Instr for LdVxDt {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.reg = ((instr & 0x0f00) >> 8) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let value = cpu.get_dt();
-        cpu.set_vx(self.reg, value);
-    }
-}
-
-impl fmt::Display for LdVxDt {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - LD V{:x}, DT", self.raw, self.reg)
-    }
-}
-
-/// *Fx0A - LD Vx, K* :: Wait for a key press, store the value of the key in Vx.
-///
-/// All execution stops until a key is pressed, then the value of that key is
-/// stored in Vx.
-#[derive(Default)]
-struct LdVxK {
-    raw: u16,
-    reg: usize,
-}
-
-impl Instr for LdVxK {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.reg = ((instr & 0x0f00) >> 8) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        cpu.wait_for_input(self.reg);
-    }
-}
-
-impl fmt::Display for LdVxK {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - LD V{:x}, K", self.raw, self.reg)
-    }
-}
-
-/// *Fx15 - LD DT, Vx* :: Set delay timer = Vx.
-///
-/// DT is set equal to the value of Vx.
-#[derive(Default)]
-struct LdDt {
-    raw: u16,
-    reg: usize,
-}
-
-impl Instr for LdDt {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.reg = ((instr & 0x0f00) >> 8) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let value = cpu.get_vx(self.reg);
-        cpu.set_dt(value);
-    }
-}
-
-impl fmt::Display for LdDt {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - LD DT, V{:x}", self.raw, self.reg)
-    }
-}
-
-/// *Fx1E - ADD I, Vx* :: Set I = I + Vx.
-///
-/// The values of I and Vx are added, and the results are stored in I.
-#[derive(Default)]
-struct AddI {
-    raw: u16,
-    reg: usize,
-}
-
-impl Instr for AddI {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.reg = ((instr & 0x0f00) >> 8) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let result = cpu.get_vx(self.reg) as u16 + cpu.get_i();
-        cpu.set_i(result);
-    }
-}
-
-impl fmt::Display for AddI {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - Add I, V{:x}", self.raw, self.reg)
-    }
-}
-
-/// *Fx29 - LD F, Vx* :: Set I = location of sprite for digit Vx.
-///
-/// The value of I is set to the location for the hexadecimal sprite corresponding
-/// to the value of Vx. See section 2.4, Display, for more information on the
-/// Chip-8 hexadecimal font.
-#[derive(Default)]
-struct LdSprite {
-    raw: u16,
-    x: usize,
-}
-
-impl Instr for LdSprite {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
-    }
-
-    fn execute(&self, cpu: &mut Cpu) {
-        let value = cpu.get_vx(self.x) as u16;
-        cpu.set_i(value * 5);
-    }
-}
-
-impl fmt::Display for LdSprite {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - Ld F, V{:x}", self.raw, self.x)
-    }
-}
-
-/// *Fx33 - LD B, Vx* :: Store BCD representation of Vx in memory locations I, I+1, and I+2.
-///
-/// The interpreter takes the decimal value of Vx, and places the hundreds digit in
-/// memory at location in I, the tens digit at location I+1, and the ones digit at
-/// location I+2.
-#[derive(Default)]
-struct LdBCD {
-    raw: u16,
-    x: usize,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quirks::Quirks;
 
-impl Instr for LdBCD {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.x = ((instr & 0x0f00) >> 8) as usize;
+    fn machine(quirks: Quirks) -> Machine {
+        let mut machine = Machine::new();
+        machine.set_quirks(quirks);
+        machine
     }
 
-    fn execute(&self, cpu: &mut Cpu) {
-        let mut value = cpu.get_vx(self.x);
-        let mem_idx = cpu.get_i() as usize;
-        cpu.set_mem(mem_idx + 2, value % 10);
-        value /= 10;
-        cpu.set_mem(mem_idx + 1, value % 10);
-        value /= 10;
-        cpu.set_mem(mem_idx, value % 10);
-    }
-}
-
-impl fmt::Display for LdBCD {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - Ld B, V{:x}", self.raw, self.x)
-    }
-}
-
-/// *Fx55 - LD [I], Vx* :: Store registers V0 through Vx in memory starting at location I.
-///
-/// The interpreter copies the values of registers V0 through Vx into memory,
-/// starting at the address in I.
-#[derive(Default)]
-struct SaveRegs {
-    raw: u16,
-    max_reg: usize,
-}
-
-impl Instr for SaveRegs {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.max_reg = ((instr & 0x0f00) >> 8) as usize;
+    /// Runs raw on machine with V1 and V2 set to a and b. Returns V1 and VF.
+    fn run(machine: &mut Machine, raw: u16, a: u8, b: u8) -> (u8, u8) {
+        machine.set_vx(0x1, a);
+        machine.set_vx(0x2, b);
+        execute(decode(raw).unwrap(), machine).unwrap();
+        (machine.get_vx(0x1), machine.get_vx(0xf))
     }
 
-    fn execute(&self, cpu: &mut Cpu) {
-        for i in 0..self.max_reg {
-            let addr = cpu.get_i() as usize + i;
-            let value = cpu.get_vx(i);
-            cpu.put_mem(addr, value);
+    #[test]
+    fn decode_rejects_unknown_words() {
+        assert_eq!(decode(0x5120).unwrap(), Opcode::SeV { x: 1, y: 2 });
+        for &raw in [0x5121, 0x5124, 0x512f, 0x8008, 0xe100, 0xf1ff].iter() {
+            match decode(raw) {
+                Err(Chip8Error::InvalidOpcode { addr: 0, raw: err }) => assert_eq!(err, raw),
+                other => panic!("{:04x} decoded to {:?}", raw, other.map_err(|err| err.to_string())),
+            }
         }
     }
-}
-
-impl fmt::Display for SaveRegs {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - Ld [I], V{:x}", self.raw, self.max_reg)
-    }
-}
-
-/// *Fx65 - LD Vx, [I]* :: Read registers V0 through Vx from memory starting at location I.
-///
-/// The interpreter reads values from memory starting at location I into registers
-/// V0 through Vx.
-#[derive(Default)]
-struct RestoreRegs {
-    raw: u16,
-    max_reg: usize,
-}
-
-impl Instr for RestoreRegs {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-        self.max_reg = ((instr & 0x0f00) >> 8) as usize;
-    }
 
-    fn execute(&self, cpu: &mut Cpu) {
-        for i in 0..self.max_reg {
-            let addr = cpu.get_i() as usize + i;
-            let value = cpu.read_mem(addr, 1)[0];
-            cpu.set_vx(i, value)
+    #[test]
+    fn add_sets_vf_on_carry() {
+        let mut machine = machine(Quirks::default());
+        assert_eq!(run(&mut machine, 0x8124, 0xff, 0x02), (0x01, 1));
+        assert_eq!(run(&mut machine, 0x8124, 0xfd, 0x02), (0xff, 0));
+    }
+
+    #[test]
+    fn sub_and_subn_set_vf_to_not_borrow() {
+        let mut machine = machine(Quirks::default());
+        assert_eq!(run(&mut machine, 0x8125, 0x05, 0x03), (0x02, 1));
+        assert_eq!(run(&mut machine, 0x8125, 0x03, 0x05), (0xfe, 0));
+        assert_eq!(run(&mut machine, 0x8125, 0x04, 0x04), (0x00, 1));
+
+        assert_eq!(run(&mut machine, 0x8127, 0x03, 0x05), (0x02, 1));
+        assert_eq!(run(&mut machine, 0x8127, 0x05, 0x03), (0xfe, 0));
+        assert_eq!(run(&mut machine, 0x8127, 0x04, 0x04), (0x00, 1));
+    }
+
+    #[test]
+    fn vf_is_written_after_the_result() {
+        let mut machine = machine(Quirks::default());
+        machine.set_vx(0xf, 0x05);
+        machine.set_vx(0x1, 0x03);
+        execute(decode(0x8f15).unwrap(), &mut machine).unwrap();
+        assert_eq!(machine.get_vx(0xf), 1);
+
+        machine.set_vx(0xf, 0xff);
+        machine.set_vx(0x1, 0x01);
+        execute(decode(0x8f14).unwrap(), &mut machine).unwrap();
+        assert_eq!(machine.get_vx(0xf), 1);
+    }
+
+    #[test]
+    fn shifts_follow_the_shift_quirk() {
+        let mut vy = machine(Quirks { shift_uses_vy: true, ..Quirks::default() });
+        assert_eq!(run(&mut vy, 0x8126, 0x00, 0x81), (0x40, 1));
+        assert_eq!(run(&mut vy, 0x8126, 0x81, 0x02), (0x01, 0));
+        assert_eq!(run(&mut vy, 0x812e, 0x00, 0x81), (0x02, 1));
+        assert_eq!(run(&mut vy, 0x812e, 0x81, 0x01), (0x02, 0));
+
+        let mut vx = machine(Quirks { shift_uses_vy: false, ..Quirks::default() });
+        assert_eq!(run(&mut vx, 0x8126, 0x81, 0x00), (0x40, 1));
+        assert_eq!(run(&mut vx, 0x8126, 0x02, 0x81), (0x01, 0));
+        assert_eq!(run(&mut vx, 0x812e, 0x81, 0x00), (0x02, 1));
+        assert_eq!(run(&mut vx, 0x812e, 0x01, 0x81), (0x02, 0));
+    }
+
+    #[test]
+    fn logic_follows_the_vf_reset_quirk() {
+        for &(vf_reset, vf) in [(true, 0), (false, 7)].iter() {
+            let mut machine = machine(Quirks { vf_reset, ..Quirks::default() });
+            for &raw in [0x8121, 0x8122, 0x8123].iter() {
+                machine.set_vx(0xf, 7);
+                assert_eq!(run(&mut machine, raw, 0x0c, 0x0a).1, vf);
+            }
         }
     }
-}
 
-impl fmt::Display for RestoreRegs {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - Ld V{:x}, [I]", self.raw, self.max_reg)
-    }
-}
-
-/// Dummy instruction. Does nothing
-#[derive(Default)]
-struct Dummy {
-    raw: u16,
-}
-
-impl Instr for Dummy {
-    fn parse(&mut self, instr: u16) {
-        self.raw = instr;
-    }
-
-    #[allow(unused_variables)]
-    fn execute(&self, cpu: &mut Cpu) {
-        // Do nothing
-    }
-}
-
-impl fmt::Display for Dummy {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} - Dummy", self.raw)
-    }
-}
-
-///
-///
-///
-///
-
-pub fn parse(raw: u16) -> Box<Instr> {
-    let mut instr: Box<Instr> = match raw & 0xf000 {
-        0x0000 => {
-            match raw {
-                0x00e0 => Box::new(Cls::default()),
-                0x00ee => Box::new(Ret::default()),
-                _ => panic!("unsupported instruction: {:04x}", raw),
-            }
-        }
-        0x1000 => Box::new(Jp::default()),
-        0x2000 => Box::new(Call::default()),
-        0x3000 => Box::new(SeB::default()),
-        0x4000 => Box::new(Sne::default()),
-        0x5000 => Box::new(SeV::default()),
-        0x6000 => Box::new(Ld::default()),
-        0x7000 => Box::new(AddB::default()),
-        0x8000 => {
-            match raw & 0x000f {
-                0x0000 => Box::new(LdReg::default()),
-                0x0001 => Box::new(Or::default()),
-                0x0002 => Box::new(And::default()),
-                0x0003 => Box::new(Xor::default()),
-                0x0004 => Box::new(AddV::default()),
-                0x0005 => Box::new(Sub::default()),
-                0x0006 => Box::new(Shr::default()),
-                0x0007 => Box::new(SubN::default()),
-                0x000e => Box::new(Shl::default()),
-                _ => panic!("unsupported instruction: {:04x}", raw),
-            }
-        }
-        0x9000 => Box::new(SneV::default()),
-        0xa000 => Box::new(LdI::default()),
-        0xb000 => Box::new(JpV0::default()),
-        0xc000 => Box::new(Rnd::default()),
-        0xd000 => Box::new(Drw::default()),
-        0xe000 => {
-            match raw & 0x00ff {
-                0x009e => Box::new(SkpVx::default()),
-                0x00a1 => Box::new(SknpVx::default()),
-                _ => panic!("unsupported instruction: {:04x}", raw),
-            }
-        }
-        0xf000 => {
-            match raw & 0x00ff {
-                0x0007 => Box::new(LdVxDt::default()),
-                0x000a => Box::new(LdVxK::default()),
-                0x0015 => Box::new(LdDt::default()),
-                0x001e => Box::new(AddI::default()),
-                0x0029 => Box::new(LdSprite::default()),
-                0x0033 => Box::new(LdBCD::default()),
-                0x0055 => Box::new(SaveRegs::default()),
-                0x0065 => Box::new(RestoreRegs::default()),
-                _ => panic!("unsupported instruction: {:04x}", raw),
+    #[test]
+    fn xochip_instructions_need_the_xochip_platform() {
+        for &platform in [Platform::Chip8, Platform::Schip].iter() {
+            let mut machine = Machine::with_platform(platform);
+            match execute(Opcode::Audio, &mut machine) {
+                Err(Chip8Error::InvalidOpcode { raw: 0xf002, .. }) => {}
+                other => panic!("AUDIO ran on {:?}: {:?}", platform, other.map_err(|err| err.to_string())),
             }
         }
-        _ => panic!("unsupported instruction: {:04x}", raw),
-    };
-
-    instr.parse(raw);
-    instr
-}
-
-pub fn execute(inst: Box<Instr>, cpu: &mut Cpu) {
-    inst.execute(cpu)
-}
 
-///
-///
-///
-///
+        let mut machine = Machine::with_platform(Platform::XoChip);
+        assert!(execute(Opcode::Audio, &mut machine).is_ok());
+    }
 
-/// *Fx18 - LD ST, Vx* :: Set sound timer = Vx.
-///
-/// ST is set equal to the value of Vx.
-#[allow(dead_code, unused_variables)]
-pub fn ld_st_vx(cpu: &mut Cpu, instr: u16) {
-    // TODO
+    #[test]
+    fn ret_with_an_empty_stack_fails() {
+        let mut machine = machine(Quirks::default());
+        match execute(Opcode::Ret, &mut machine) {
+            Err(Chip8Error::StackUnderflow { .. }) => {}
+            other => panic!("RET returned {:?}", other.map_err(|err| err.to_string())),
+        }
+    }
 }
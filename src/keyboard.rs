@@ -1,27 +1,67 @@
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Scancode};
 
-pub struct Keyboard {
-    keys: [bool; 16],
+use keymap::KeyMap;
+
+/// The emulator's own hotkeys, by symbol and by their usual position
+const RESERVED: [(Keycode, Scancode); 7] = [
+    (Keycode::Escape, Scancode::Escape),
+    (Keycode::Return, Scancode::Return),
+    (Keycode::P, Scancode::P),
+    (Keycode::Space, Scancode::Space),
+    (Keycode::F5, Scancode::F5),
+    (Keycode::F9, Scancode::F9),
+    (Keycode::Backspace, Scancode::Backspace),
+];
+
+/// A host key, by symbol or by position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostKey {
+    /// The key with this symbol in the current layout
+    Key(Keycode),
+    /// The key at this position, whatever the layout
+    Scan(Scancode),
 }
 
-impl Keyboard {
-    pub fn new() -> Keyboard {
-        Keyboard { keys: [false; 16] }
+impl HostKey {
+    /// Looks up a key by its SDL name, by position with a `scan:` prefix
+    pub fn from_name(name: &str) -> Option<HostKey> {
+        if let Some(name) = name.strip_prefix("scan:") {
+            return Scancode::from_name(name).map(HostKey::Scan);
+        }
+
+        // The keycode of a character key is the character itself
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => c.to_lowercase().next().and_then(|c| Keycode::from_i32(c as i32)).map(HostKey::Key),
+            _ => Keycode::from_name(name).map(HostKey::Key),
+        }
     }
 
-    pub fn pressed(&self, key: usize) -> bool {
-        self.keys[key]
+    /// Whether this is one of the keys the emulator keeps for itself
+    pub fn is_reserved(self) -> bool {
+        RESERVED.iter().any(|&(key, scan)| self == HostKey::Key(key) || self == HostKey::Scan(scan))
     }
 
-    pub fn press(&mut self, key: Keycode, state: bool) {
-        let index = self.key_to_index(key);
-        if index <= 0xf {
-            self.keys[index] = state;
+    fn matches(self, keycode: Option<Keycode>, scancode: Option<Scancode>) -> bool {
+        match self {
+            HostKey::Key(key) => keycode == Some(key),
+            HostKey::Scan(scan) => scancode == Some(scan),
         }
     }
+}
+
+/// Translates host key presses into the machine's hex keypad
+pub struct Keyboard {
+    /// The host keys of each hex key
+    keys: [Vec<HostKey>; 16],
+    /// Host keys held down, by position, with the hex key they pressed
+    held: Vec<(Scancode, usize)>,
+}
 
+impl Default for Keyboard {
     /**
-     * Maps the following keyboard configuration
+     * Maps the following keyboard configuration, by key position so that it
+     * holds on any layout
      *  *---------------*    *---------------*
      *  | 1 | 2 | 3 | 4 |    | 1 | 2 | 3 | C |
      *  | Q | W | E | R |    | 4 | 5 | 6 | D |
@@ -29,25 +69,76 @@ impl Keyboard {
      *  | Z | X | C | V |    | A | 0 | B | F |
      *  *---------------*    *---------------*
      */
-    fn key_to_index(&self, key: Keycode) -> usize {
-        match key {
-            Keycode::Num1 => 0x1,
-            Keycode::Num2 => 0x2,
-            Keycode::Num3 => 0x3,
-            Keycode::Num4 => 0xc,
-            Keycode::Q => 0x4,
-            Keycode::W => 0x5,
-            Keycode::E => 0x6,
-            Keycode::R => 0xd,
-            Keycode::A => 0x7,
-            Keycode::S => 0x8,
-            Keycode::D => 0x9,
-            Keycode::F => 0xe,
-            Keycode::Z => 0xa,
-            Keycode::X => 0x0,
-            Keycode::C => 0xb,
-            Keycode::V => 0xf,
-            _ => 99,
+    fn default() -> Keyboard {
+        let positions = [
+            Scancode::X, Scancode::Num1, Scancode::Num2, Scancode::Num3,
+            Scancode::Q, Scancode::W, Scancode::E, Scancode::A,
+            Scancode::S, Scancode::D, Scancode::Z, Scancode::C,
+            Scancode::Num4, Scancode::R, Scancode::F, Scancode::V,
+        ];
+
+        let mut keys: [Vec<HostKey>; 16] = Default::default();
+        for (keys, &scan) in keys.iter_mut().zip(positions.iter()) {
+            keys.push(HostKey::Scan(scan));
+        }
+        Keyboard { keys, held: vec![] }
+    }
+}
+
+impl Keyboard {
+    pub fn new() -> Keyboard {
+        Keyboard::default()
+    }
+
+    /// The default bindings, with those of map in place of them. A host key
+    /// bound again leaves the hex key it was bound to.
+    pub fn with_map(map: &KeyMap) -> ::std::result::Result<Keyboard, String> {
+        let mut keyboard = Keyboard::new();
+        for (key, names) in map.bindings() {
+            let host_keys = names.iter()
+                .map(|name| HostKey::from_name(name).ok_or_else(|| format!("unknown key {}", name)))
+                .collect::<::std::result::Result<Vec<HostKey>, String>>()?;
+            if let Some((name, _)) = names.iter().zip(host_keys.iter()).find(|&(_, key)| key.is_reserved()) {
+                return Err(format!("reserved key {}", name));
+            }
+            for keys in keyboard.keys.iter_mut() {
+                keys.retain(|host_key| !host_keys.contains(host_key));
+            }
+            keyboard.keys[key] = host_keys;
+        }
+        Ok(keyboard)
+    }
+
+    /// The hex key bound to a host key. Bindings by symbol win over bindings
+    /// by position, so that a key bound by name is not taken by a default.
+    pub fn key_to_index(&self, keycode: Option<Keycode>, scancode: Option<Scancode>) -> Option<usize> {
+        let bound = |by_symbol: bool| {
+            self.keys.iter().position(|keys| {
+                keys.iter().any(|&key| matches!(key, HostKey::Key(_)) == by_symbol && key.matches(keycode, scancode))
+            })
+        };
+        bound(true).or_else(|| bound(false))
+    }
+
+    /// Follows a host key going down or up. Returns the hex key it pressed
+    /// or released; a hex key with several host keys stays pressed until
+    /// the last of them is released.
+    pub fn press(&mut self, keycode: Option<Keycode>, scancode: Option<Scancode>, down: bool) -> Option<(usize, bool)> {
+        let scancode = scancode?;
+        if down {
+            let index = self.key_to_index(keycode, Some(scancode))?;
+            if !self.held.contains(&(scancode, index)) {
+                self.held.push((scancode, index));
+            }
+            Some((index, true))
+        } else {
+            // The symbol of a held key may have changed with the modifiers
+            let position = self.held.iter().position(|&(held, _)| held == scancode)?;
+            let (_, index) = self.held.remove(position);
+            if self.held.iter().any(|&(_, held)| held == index) {
+                return None;
+            }
+            Some((index, false))
         }
     }
 }
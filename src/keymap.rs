@@ -0,0 +1,152 @@
+//! Host key bindings of the hex keypad.
+//!
+//! Host keys are named as SDL names them: `w`, `Up`, `Keypad 8`. A name is
+//! matched against the symbol of the key pressed, which depends on the
+//! keyboard layout; with a `scan:` prefix, as in `scan:W`, it is matched
+//! against the key's position instead, which does not.
+//!
+//! Game controller buttons have a `pad:` prefix: `pad:a`, `pad:dpup`,
+//! `pad:leftshoulder`. A stick or trigger pushed past the threshold counts as
+//! a button too, named after its axis and direction: `pad:leftx-`,
+//! `pad:lefty+`, `pad:righttrigger+`.
+//!
+//! Bindings files are TOML, with the host keys of each hex key:
+//!
+//! ```toml
+//! 5 = ["w", "Up", "pad:dpup"]
+//! 8 = ["s", "Down", "pad:dpdown"]
+//! 7 = ["a", "Left", "pad:dpleft"]
+//! 9 = ["d", "Right", "pad:dpright"]
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::Value as Toml;
+
+use error::{Chip8Error, Result};
+use romdb;
+
+/// Prefix of the names of game controller buttons
+pub const PAD_PREFIX: &str = "pad:";
+
+/// Host keys bound to hex keys. Hex keys without bindings keep the default
+/// ones, for the keyboard and game controllers separately.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyMap {
+    keys: BTreeMap<usize, Vec<String>>,
+    buttons: BTreeMap<usize, Vec<String>>,
+}
+
+impl KeyMap {
+    pub fn new() -> KeyMap {
+        KeyMap::default()
+    }
+
+    /// Binds the characters of layout, which must be 16, to the hex keys 0 to
+    /// F in order
+    pub fn from_layout(layout: &str) -> Option<KeyMap> {
+        let layout: Vec<char> = layout.chars().collect();
+        if layout.len() != 16 {
+            return None;
+        }
+
+        let mut map = KeyMap::new();
+        for (key, c) in layout.into_iter().enumerate() {
+            map.bind(key, vec![c.to_string()]);
+        }
+        Some(map)
+    }
+
+    /// Reads a bindings file
+    pub fn read(path: &Path) -> Result<KeyMap> {
+        let file = path.display().to_string();
+        let root: Toml = fs::read_to_string(path)?
+            .parse()
+            .map_err(|err: ::toml::de::Error| invalid(&file, err.to_string()))?;
+        let table = root.as_table().ok_or_else(|| invalid(&file, "expected a table".to_string()))?;
+        KeyMap::from_toml(table, &file)
+    }
+
+    /// Reads the bindings in a TOML table, named file in errors
+    pub fn from_toml(table: &::toml::value::Table, file: &str) -> Result<KeyMap> {
+        let mut map = KeyMap::new();
+        for (key, value) in table.iter() {
+            let index = parse_key(key).ok_or_else(|| invalid(file, format!("not a hex key: {}", key)))?;
+            let names = match *value {
+                Toml::String(ref name) => vec![name.clone()],
+                Toml::Array(ref names) => {
+                    names.iter()
+                        .map(|name| name.as_str().map(str::to_string))
+                        .collect::<Option<Vec<String>>>()
+                        .ok_or_else(|| invalid(file, format!("the keys of {} must be names", key)))?
+                }
+                _ => return Err(invalid(file, format!("the keys of {} must be a list of names", key))),
+            };
+            map.bind(index, names);
+        }
+        Ok(map)
+    }
+
+    /// Parses a `<hex key>=<host key>[,<host key>...]` binding, as given on
+    /// the command line
+    pub fn parse_binding(binding: &str) -> Option<(usize, Vec<String>)> {
+        let mut parts = binding.splitn(2, '=');
+        let key = parse_key(parts.next()?)?;
+        let names: Vec<String> = parts.next()?.split(',').map(|name| name.trim().to_string()).collect();
+        if names.iter().any(String::is_empty) {
+            return None;
+        }
+        Some((key, names))
+    }
+
+    /// Binds names to key, replacing its keyboard bindings if there are
+    /// keys among them, and its controller bindings if there are buttons.
+    /// No names at all unbind the key.
+    pub fn bind(&mut self, key: usize, names: Vec<String>) {
+        let (buttons, keys): (Vec<String>, Vec<String>) = names.into_iter()
+            .partition(|name| name.starts_with(PAD_PREFIX));
+        let unbind = keys.is_empty() && buttons.is_empty();
+
+        if unbind || !keys.is_empty() {
+            self.keys.insert(key, keys);
+        }
+        if unbind || !buttons.is_empty() {
+            self.buttons.insert(key, buttons);
+        }
+    }
+
+    /// Applies the bindings of other over these, key by key
+    pub fn extend(&mut self, other: &KeyMap) {
+        for (&key, names) in other.keys.iter() {
+            self.keys.insert(key, names.clone());
+        }
+        for (&key, names) in other.buttons.iter() {
+            self.buttons.insert(key, names.clone());
+        }
+    }
+
+    /// The hex keys bound to keyboard keys, with the names of these
+    pub fn bindings(&self) -> impl Iterator<Item = (usize, &[String])> {
+        self.keys.iter().map(|(&key, names)| (key, names.as_slice()))
+    }
+
+    /// The hex keys bound to controller buttons, with the names of these
+    pub fn button_bindings(&self) -> impl Iterator<Item = (usize, &[String])> {
+        self.buttons.iter().map(|(&key, names)| (key, names.as_slice()))
+    }
+}
+
+/// The user's bindings file, `keymap.toml` in the configuration directory
+pub fn user_keymap_path() -> Option<PathBuf> {
+    romdb::config_dir().map(|dir| dir.join("keymap.toml"))
+}
+
+fn parse_key(key: &str) -> Option<usize> {
+    usize::from_str_radix(key.trim(), 16).ok().filter(|&key| key < 16)
+}
+
+fn invalid(file: &str, reason: String) -> Chip8Error {
+    Chip8Error::InvalidConfig { file: file.to_string(), reason }
+}
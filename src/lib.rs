@@ -0,0 +1,48 @@
+//! Chip-8 interpreter.
+//!
+//! The `Machine` is a headless core. A `Cpu` drives it through pluggable
+//! video, input and audio backends; the SDL backends used by the `chip-8`
+//! binary live behind the `sdl` feature.
+
+extern crate itertools;
+extern crate rand;
+extern crate serde_json;
+extern crate sha1_smol;
+extern crate toml;
+#[cfg(feature = "sdl")]
+extern crate sdl2;
+
+pub mod asm;
+pub mod backend;
+pub mod cfg;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod framebuffer;
+pub mod gdb;
+pub mod instr;
+pub mod keymap;
+pub mod machine;
+pub mod movie;
+pub mod octo;
+pub mod platform;
+pub mod profile;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod romdb;
+pub mod spec;
+pub mod state;
+pub mod trace;
+
+#[cfg(feature = "sdl")]
+pub mod gamepad;
+#[cfg(feature = "sdl")]
+pub mod keyboard;
+
+pub use cpu::Cpu;
+pub use error::{Chip8Error, Result};
+pub use machine::Machine;
+pub use platform::Platform;
+pub use quirks::Quirks;
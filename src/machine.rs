@@ -0,0 +1,628 @@
+use std::fmt;
+use std::io::{Read, Write};
+
+use itertools::join;
+
+use error::{Chip8Error, Result};
+use framebuffer::Framebuffer;
+use instr;
+use platform::Platform;
+use quirks::Quirks;
+use rng::Rng;
+use spec;
+use state;
+
+/// Headless Chip-8 core.
+///
+/// Owns every piece of interpreter state: registers, memory, stack, timers,
+/// the framebuffer and the keypad. It knows nothing about windows or input
+/// devices, so it can be driven by a frontend, a test runner or a tool alike.
+pub struct Machine {
+    // Connected systems
+    framebuffer: Framebuffer,
+    keypad: [bool; 16],
+    platform: Platform,
+    quirks: Quirks,
+    rng: Rng,
+    waiting_vblank: bool,
+    halted: bool,
+
+    // Registers
+    r_vx: [u8; 16],
+    r_i: u16,
+    r_dt: u8,
+    r_st: u8,
+    r_pc: u16,
+    r_sp: u8,
+    stack: [u16; 16],
+    mem: Vec<u8>,
+    rpl: [u8; 16],
+
+    // XO-CHIP audio
+    audio_pattern: Option<[u8; spec::AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+}
+
+impl Machine {
+    /// Initialize the machine with all registers at 0 and the font loaded
+    pub fn new() -> Machine {
+        Machine::with_platform(Platform::default())
+    }
+
+    /// Initialize a machine for the given platform, with the memory size and
+    /// quirks it calls for
+    pub fn with_platform(platform: Platform) -> Machine {
+        let mut mem = vec![0u8; platform.mem_size()];
+
+        Machine::load_sprites(&mut mem);
+
+        Machine {
+            framebuffer: Framebuffer::new(),
+            keypad: [false; 16],
+            platform,
+            quirks: platform.quirks(),
+            rng: Rng::from_entropy(),
+            waiting_vblank: false,
+            halted: false,
+
+            r_vx: [0; 16],
+            r_i: 0,
+            r_dt: 0,
+            r_st: 0,
+            r_pc: spec::PROGRAM_START as u16,
+            r_sp: 0,
+            stack: [0; 16],
+            mem,
+            rpl: [0; 16],
+
+            audio_pattern: None,
+            pitch: spec::DEFAULT_PITCH,
+        }
+    }
+
+    /// Load the built in font sprites: the 4x5 hex digits, followed by the
+    /// SUPER-CHIP 8x10 hex digits
+    #[rustfmt::skip]
+    fn load_sprites(mem: &mut [u8]) {
+        let sprites = [
+            0xf0, 0x90, 0x90, 0x90, 0xf0, // 0
+            0x20, 0x60, 0x20, 0x20, 0x70, // 1
+            0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
+            0xf0, 0x10, 0xf0, 0x10, 0xf0, // 3
+            0x90, 0x90, 0xf0, 0x10, 0x10, // 4
+            0xf0, 0x80, 0xf0, 0x10, 0xf0, // 5
+            0xf0, 0x80, 0xf0, 0x90, 0xf0, // 6
+            0xf0, 0x10, 0x20, 0x40, 0x40, // 7
+            0xf0, 0x90, 0xf0, 0x90, 0xf0, // 8
+            0xf0, 0x90, 0xf0, 0x10, 0xf0, // 9
+            0xf0, 0x90, 0xf0, 0x90, 0x90, // A
+            0xe0, 0x90, 0xe0, 0x90, 0xe0, // B
+            0xf0, 0x80, 0x80, 0x80, 0xf0, // C
+            0xe0, 0x90, 0x90, 0x90, 0xe0, // D
+            0xf0, 0x80, 0xf0, 0x80, 0xf0, // E
+            0xf0, 0x80, 0xf0, 0x80, 0x80, // F
+        ];
+
+        let big_sprites = [
+            0x3c, 0x7e, 0xe7, 0xc3, 0xc3, 0xc3, 0xc3, 0xe7, 0x7e, 0x3c, // 0
+            0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3c, // 1
+            0x3e, 0x7f, 0xc3, 0x06, 0x0c, 0x18, 0x30, 0x60, 0xff, 0xff, // 2
+            0x3c, 0x7e, 0xc3, 0x03, 0x0e, 0x0e, 0x03, 0xc3, 0x7e, 0x3c, // 3
+            0x06, 0x0e, 0x1e, 0x36, 0x66, 0xc6, 0xff, 0xff, 0x06, 0x06, // 4
+            0xff, 0xff, 0xc0, 0xc0, 0xfc, 0xfe, 0x03, 0xc3, 0x7e, 0x3c, // 5
+            0x3e, 0x7c, 0xe0, 0xc0, 0xfc, 0xfe, 0xc3, 0xc3, 0x7e, 0x3c, // 6
+            0xff, 0xff, 0x03, 0x06, 0x0c, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+            0x3c, 0x7e, 0xc3, 0xc3, 0x7e, 0x7e, 0xc3, 0xc3, 0x7e, 0x3c, // 8
+            0x3c, 0x7e, 0xc3, 0xc3, 0x7f, 0x3f, 0x03, 0x03, 0x3e, 0x7c, // 9
+            0x18, 0x3c, 0x66, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xc3, // A
+            0xfc, 0xfe, 0xc3, 0xc3, 0xfe, 0xfe, 0xc3, 0xc3, 0xfe, 0xfc, // B
+            0x3c, 0x7e, 0xc3, 0xc0, 0xc0, 0xc0, 0xc0, 0xc3, 0x7e, 0x3c, // C
+            0xfc, 0xfe, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xfe, 0xfc, // D
+            0xff, 0xff, 0xc0, 0xc0, 0xfc, 0xfc, 0xc0, 0xc0, 0xff, 0xff, // E
+            0xff, 0xff, 0xc0, 0xc0, 0xfc, 0xfc, 0xc0, 0xc0, 0xc0, 0xc0, // F
+        ];
+
+        let font = spec::FONT_START;
+        mem[font..(font + sprites.len())].copy_from_slice(&sprites);
+
+        let big_font = spec::BIG_FONT_START;
+        mem[big_font..(big_font + big_sprites.len())].copy_from_slice(&big_sprites);
+    }
+
+    /// Dumps the rom into memory, starting at the program start address
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<()> {
+        let start = spec::PROGRAM_START;
+        let max = self.mem.len() - start;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max });
+        }
+
+        self.mem[start..(start + rom.len())].copy_from_slice(rom);
+        Ok(())
+    }
+
+    /// Writes a save state of the whole machine, keypad excepted
+    pub fn save_state(&self, w: &mut dyn Write) -> Result<()> {
+        state::write_header(w)?;
+
+        state::write_platform(w, self.platform)?;
+        state::write_quirks(w, self.quirks)?;
+        state::write_bool(w, self.waiting_vblank)?;
+        state::write_bool(w, self.halted)?;
+
+        w.write_all(&self.r_vx)?;
+        state::write_u16(w, self.r_i)?;
+        state::write_u8(w, self.r_dt)?;
+        state::write_u8(w, self.r_st)?;
+        state::write_u16(w, self.r_pc)?;
+        state::write_u8(w, self.r_sp)?;
+        for &addr in self.stack.iter() {
+            state::write_u16(w, addr)?;
+        }
+        state::write_bytes(w, &self.mem)?;
+        w.write_all(&self.rpl)?;
+
+        state::write_bool(w, self.audio_pattern.is_some())?;
+        w.write_all(&self.audio_pattern.unwrap_or_default())?;
+        state::write_u8(w, self.pitch)?;
+
+        self.framebuffer.save_state(w)?;
+        self.rng.save_state(w)
+    }
+
+    /// Restores a save state written by `save_state`. The keypad is left as
+    /// it is, and so is the machine if the state cannot be read.
+    pub fn load_state(&mut self, r: &mut dyn Read) -> Result<()> {
+        state::read_header(r)?;
+
+        let mut machine = Machine::with_platform(state::read_platform(r)?);
+        machine.keypad = self.keypad;
+        machine.quirks = state::read_quirks(r)?;
+        machine.waiting_vblank = state::read_bool(r)?;
+        machine.halted = state::read_bool(r)?;
+
+        machine.r_vx.copy_from_slice(&state::read_bytes(r, 16)?);
+        machine.r_i = state::read_u16(r)?;
+        machine.r_dt = state::read_u8(r)?;
+        machine.r_st = state::read_u8(r)?;
+        machine.r_pc = state::read_u16(r)?;
+        machine.r_sp = state::read_u8(r)?;
+        if machine.r_sp as usize > machine.stack.len() {
+            return Err(state::invalid("stack pointer out of range"));
+        }
+        for addr in machine.stack.iter_mut() {
+            *addr = state::read_u16(r)?;
+        }
+
+        let mem = state::read_buffer(r, spec::XO_MEM_SIZE)?;
+        if mem.len() != machine.mem.len() {
+            return Err(state::invalid("memory size does not match the platform"));
+        }
+        machine.mem = mem;
+        machine.rpl.copy_from_slice(&state::read_bytes(r, 16)?);
+
+        let has_pattern = state::read_bool(r)?;
+        let pattern = state::read_bytes(r, spec::AUDIO_PATTERN_SIZE)?;
+        if has_pattern {
+            let mut audio_pattern = [0u8; spec::AUDIO_PATTERN_SIZE];
+            audio_pattern.copy_from_slice(&pattern);
+            machine.audio_pattern = Some(audio_pattern);
+        }
+        machine.pitch = state::read_u8(r)?;
+
+        machine.framebuffer = Framebuffer::load_state(r)?;
+
+        machine.rng = Rng::load_state(r)?;
+
+        *self = machine;
+        Ok(())
+    }
+
+    /// Fetches, decodes and executes the instruction at PC
+    pub fn step(&mut self) -> Result<()> {
+        let addr = self.r_pc;
+
+        self.read_instr()
+            .and_then(instr::decode)
+            .and_then(|cmd| instr::execute(cmd, self))
+            .map_err(|err| err.at(addr))
+    }
+
+    /// Reads the next instruction on the rom.
+    /// The position is set by the current value of PC
+    pub fn read_instr(&mut self) -> Result<u16> {
+        let bytes = self.read_mem(self.r_pc as usize, 2)?;
+        let instr = ((bytes[0] as u16) << 8) | bytes[1] as u16;
+
+        self.inc_pc();
+        Ok(instr)
+    }
+
+    /// Read n bytes from memory, starting at addr
+    pub fn read_mem(&self, addr: usize, n: usize) -> Result<Vec<u8>> {
+        let end = addr.checked_add(n);
+        match end.and_then(|end| self.mem.get(addr..end)) {
+            Some(bytes) => Ok(bytes.to_vec()),
+            None => Err(self.out_of_bounds(end.unwrap_or(usize::MAX).saturating_sub(1))),
+        }
+    }
+
+    /// Read n bytes from memory, starting at addr
+    pub fn put_mem(&mut self, addr: usize, value: u8) -> Result<()> {
+        self.set_mem(addr, value)
+    }
+
+    /// Sets the PC register to a given address.
+    pub fn get_pc(&self) -> u16 {
+        self.r_pc
+    }
+
+    /// Decrements the PC to the previous instruction
+    pub fn dec_pc(&mut self) {
+        let cur_pc = self.r_pc;
+        self.set_pc(cur_pc.wrapping_sub(2));
+    }
+
+    /// Increments the PC to the next instruction
+    pub fn inc_pc(&mut self) {
+        let cur_pc = self.r_pc;
+        self.set_pc(cur_pc.wrapping_add(2));
+    }
+
+    /// Sets the PC register to a given address.
+    pub fn set_pc(&mut self, addr: u16) {
+        self.r_pc = addr;
+    }
+
+    /// Skips the next instruction. On XO-CHIP, `F000 nnnn` is four bytes long
+    /// and is skipped as a whole.
+    pub fn skip_next(&mut self) {
+        let pc = self.r_pc as usize;
+        let long = self.platform == Platform::XoChip &&
+                   self.mem.get(pc..(pc + 2)) == Some(&[0xf0, 0x00][..]);
+
+        self.inc_pc();
+        if long {
+            self.inc_pc();
+        }
+    }
+
+    /// Gets the value of the SP register, the depth of the stack
+    pub fn get_sp(&self) -> u8 {
+        self.r_sp
+    }
+
+    /// Sets the SP register. Fails if it would point past the stack.
+    pub fn set_sp(&mut self, value: u8) -> Result<()> {
+        if value as usize > self.stack.len() {
+            return Err(Chip8Error::StackOverflow { addr: self.r_pc });
+        }
+
+        self.r_sp = value;
+        Ok(())
+    }
+
+    /// Put value on top of the stack and increment the stack pointer. All 16
+    /// levels can be used.
+    pub fn push_stack(&mut self, value: u16) -> Result<()> {
+        if self.r_sp as usize >= self.stack.len() {
+            return Err(Chip8Error::StackOverflow { addr: self.r_pc });
+        }
+
+        self.stack[self.r_sp as usize] = value;
+        self.r_sp += 1;
+        Ok(())
+    }
+
+    /// Decrements the stack pointer and gets the value on top of the stack
+    pub fn pop_stack(&mut self) -> Result<u16> {
+        if self.r_sp == 0 {
+            return Err(Chip8Error::StackUnderflow { addr: self.r_pc });
+        }
+
+        self.r_sp -= 1;
+        Ok(self.stack[self.r_sp as usize])
+    }
+
+    /// Sets the address in memory to a given value, where x in the given index.
+    pub fn set_mem(&mut self, reg: usize, value: u8) -> Result<()> {
+        if reg >= self.mem.len() {
+            return Err(self.out_of_bounds(reg));
+        }
+
+        self.mem[reg] = value;
+        Ok(())
+    }
+
+    fn out_of_bounds(&self, index: usize) -> Chip8Error {
+        Chip8Error::MemoryOutOfBounds { addr: self.r_pc, index }
+    }
+
+    /// Gets the value of the Vx register.
+    pub fn get_vx(&self, reg: usize) -> u8 {
+        self.r_vx[reg]
+    }
+
+    /// Sets the Vx register to a given value, where x in the given index.
+    pub fn set_vx(&mut self, reg: usize, value: u8) {
+        self.r_vx[reg] = value;
+    }
+
+    /// Gets the value of the Vx register.
+    pub fn get_i(&self) -> u16 {
+        self.r_i
+    }
+
+    /// Sets the i register to a given value.
+    pub fn set_i(&mut self, value: u16) {
+        self.r_i = value;
+    }
+
+    /// Gets the value of the DT register
+    pub fn get_dt(&self) -> u8 {
+        self.r_dt
+    }
+
+    /// Decreases the DT register by 1, saturating at 0.
+    pub fn dec_dt(&mut self) {
+        let value = self.r_dt.saturating_sub(1);
+        self.set_dt(value);
+    }
+
+    /// Sets the DT register to a given value
+    pub fn set_dt(&mut self, value: u8) {
+        self.r_dt = value;
+    }
+
+    /// Gets the value of the ST register
+    pub fn get_st(&self) -> u8 {
+        self.r_st
+    }
+
+    /// Decreases the ST register by 1, saturating at 0.
+    pub fn dec_st(&mut self) {
+        let value = self.r_st.saturating_sub(1);
+        self.set_st(value);
+    }
+
+    /// Sets the ST register to a given value
+    pub fn set_st(&mut self, value: u8) {
+        self.r_st = value;
+    }
+
+    /// Counts both timers down by one tick. Meant to be called at 60 Hz, on
+    /// the vertical blank.
+    pub fn tick_timers(&mut self) {
+        self.dec_dt();
+        self.dec_st();
+        self.waiting_vblank = false;
+    }
+
+    /// Whether execution is blocked until the next frame.
+    /// See `Quirks::display_wait`.
+    pub fn waiting_vblank(&self) -> bool {
+        self.waiting_vblank
+    }
+
+    /// Blocks execution until the next call to `tick_timers`
+    pub fn wait_vblank(&mut self) {
+        self.waiting_vblank = true;
+    }
+
+    /// Whether the program has exited through `00FD`
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Stops the program
+    pub fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    /// Gets the value of a SUPER-CHIP RPL user flag
+    pub fn get_rpl(&self, reg: usize) -> u8 {
+        self.rpl[reg]
+    }
+
+    /// Sets the value of a SUPER-CHIP RPL user flag
+    pub fn set_rpl(&mut self, reg: usize, value: u8) {
+        self.rpl[reg] = value;
+    }
+
+    /// Gets the platform the machine emulates
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// Gets the XO-CHIP audio pattern, if the program has loaded one
+    pub fn audio_pattern(&self) -> Option<[u8; spec::AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern
+    }
+
+    /// Sets the XO-CHIP audio pattern, played instead of the buzzer tone
+    pub fn set_audio_pattern(&mut self, pattern: [u8; spec::AUDIO_PATTERN_SIZE]) {
+        self.audio_pattern = Some(pattern);
+    }
+
+    /// Gets the XO-CHIP playback rate of the audio pattern
+    pub fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Sets the XO-CHIP playback rate of the audio pattern
+    pub fn set_pitch(&mut self, value: u8) {
+        self.pitch = value;
+    }
+
+    /// Gets the random number generator used by `Cxkk`
+    pub fn rng(&self) -> &Rng {
+        &self.rng
+    }
+
+    /// Replaces the random number generator used by `Cxkk`
+    pub fn set_rng(&mut self, rng: Rng) {
+        self.rng = rng;
+    }
+
+    /// Produces the next random byte
+    pub fn random_byte(&mut self) -> u8 {
+        self.rng.next_byte()
+    }
+
+    /// Gets the active compatibility quirks
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Sets the compatibility quirks
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Get a reference to the framebuffer
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// Get a mutable reference to the framebuffer
+    pub fn get_framebuffer(&mut self) -> &mut Framebuffer {
+        &mut self.framebuffer
+    }
+
+    /// Whether the given key of the hex keypad is down
+    pub fn pressed(&self, key: usize) -> bool {
+        self.keypad[key]
+    }
+
+    /// Sets the state of a key of the hex keypad
+    pub fn set_key(&mut self, key: usize, state: bool) {
+        self.keypad[key] = state;
+    }
+
+    pub fn wait_for_input(&mut self, reg: usize) {
+        for i in 0..16 {
+            if self.keypad[i] {
+                self.r_vx[reg] = i as u8;
+                return;
+            }
+        }
+        self.dec_pc()
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Machine {
+        Machine::new()
+    }
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r_vx = join(self.r_vx.iter().map(|v| format!("{:02x}", v)), ", ");
+        let stack = join(self.stack.iter().map(|v| format!("{:04x}", v)), ", ");
+
+        write!(f,
+               "CPU [
+    r_vx: {},
+    r_i: {:04x},
+    r_dt: {:02x},
+    r_st: {:02x},
+    r_pc: {:04x},
+    r_sp: {:02x},
+    stack: {},
+]",
+               r_vx,
+               self.r_i,
+               self.r_dt,
+               self.r_st,
+               self.r_pc,
+               self.r_sp,
+               stack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_of(machine: &Machine) -> Vec<u8> {
+        let mut state = vec![];
+        machine.save_state(&mut state).unwrap();
+        state
+    }
+
+    /// An XO-CHIP machine with something in every part of its state
+    fn busy_machine() -> Machine {
+        let mut machine = Machine::with_platform(Platform::XoChip);
+        machine.set_quirks(Quirks::chip48());
+        machine.load_rom(&[0x60, 0x05, 0x12, 0x00]).unwrap();
+        for reg in 0..16 {
+            machine.set_vx(reg, reg as u8 * 3);
+            machine.set_rpl(reg, 0xf0 | reg as u8);
+        }
+        machine.set_i(0x1234);
+        machine.set_dt(7);
+        machine.set_st(9);
+        machine.set_pc(0x0206);
+        machine.push_stack(0x0202).unwrap();
+        machine.push_stack(0x0404).unwrap();
+        machine.set_mem(0xfff0, 0xaa).unwrap();
+        machine.set_audio_pattern([0x55; spec::AUDIO_PATTERN_SIZE]);
+        machine.set_pitch(80);
+        machine.get_framebuffer().set_hires(true);
+        machine.get_framebuffer().flip(100, 50, 1);
+        machine.set_rng(Rng::new(42));
+        machine.random_byte();
+        machine.wait_vblank();
+        machine
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let machine = busy_machine();
+        let state = state_of(&machine);
+
+        let mut restored = Machine::new();
+        restored.load_state(&mut &state[..]).unwrap();
+        assert_eq!(state_of(&restored), state);
+
+        assert_eq!(restored.platform(), Platform::XoChip);
+        assert_eq!(restored.quirks(), Quirks::chip48());
+        assert_eq!((restored.get_pc(), restored.get_sp(), restored.get_i()), (0x0206, 2, 0x1234));
+        assert_eq!(restored.pop_stack().unwrap(), 0x0404);
+        assert_eq!(restored.read_mem(0xfff0, 1).unwrap(), vec![0xaa]);
+        assert_eq!(restored.framebuffer().get_pixel(100, 50), 1);
+        assert!(restored.waiting_vblank());
+
+        let mut original = machine;
+        assert_eq!(restored.random_byte(), original.random_byte());
+    }
+
+    #[test]
+    fn load_keeps_the_keypad() {
+        let state = state_of(&busy_machine());
+        let mut machine = Machine::new();
+        machine.set_key(0x5, true);
+        machine.load_state(&mut &state[..]).unwrap();
+        assert!(machine.pressed(0x5));
+    }
+
+    #[test]
+    fn bad_states_leave_the_machine_alone() {
+        let mut machine = Machine::new();
+        machine.load_rom(&[0x60, 0x05]).unwrap();
+        let before = state_of(&machine);
+
+        let state = state_of(&busy_machine());
+        let mut wrong_magic = state.clone();
+        wrong_magic[0] ^= 0xff;
+        let mut wrong_version = state.clone();
+        wrong_version[state::MAGIC.len()] = state::VERSION + 1;
+        let truncated = &state[..state.len() - 1];
+
+        for bad in [&wrong_magic[..], &wrong_version[..], truncated].iter() {
+            assert!(machine.load_state(&mut &bad[..]).is_err());
+            assert_eq!(state_of(&machine), before);
+        }
+    }
+}
@@ -1,34 +1,503 @@
-extern crate itertools;
-extern crate rand;
+extern crate chip_8;
 extern crate sdl2;
-extern crate time;
 
 use std::env;
 use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read};
+use std::path::PathBuf;
+use std::process;
 
-mod cpu;
-mod display;
-mod instr;
-mod keyboard;
-mod spec;
+use chip_8::backend::null::{NullAudio, NullInput, NullVideo};
+use chip_8::backend::sdl::{SdlAudio, SdlInput, SdlVideo};
+use chip_8::backend::{AudioSink, InputSource, VideoSink};
+use chip_8::gdb::GdbStub;
+use chip_8::gamepad::{self, Gamepad};
+use chip_8::keyboard::Keyboard;
+use chip_8::keymap::{self, KeyMap};
+use chip_8::movie::{self, Movie};
+use chip_8::octo;
+use chip_8::platform;
+use chip_8::profile::Profiler;
+use chip_8::quirks;
+use chip_8::rng::Rng;
+use chip_8::romdb::{self, RomConfig};
+use chip_8::spec;
+use chip_8::trace::Tracer;
+use chip_8::{Chip8Error, Cpu, Machine, Platform, Quirks, Result};
+
+const USAGE: &str = "Usage: chip-8 [options] <rom>";
+
+struct Options {
+    file_name: String,
+    platform: Option<Platform>,
+    preset: Option<Quirks>,
+    quirk_overrides: Vec<(String, bool)>,
+    rom_config: Option<String>,
+    scale: u32,
+    ips: Option<u32>,
+    fullscreen: bool,
+    headless: bool,
+    palette: Option<[u32; 16]>,
+    beep_frequency: f32,
+    volume: f32,
+    keymap: KeyMap,
+    keymap_file: Option<String>,
+    stick_threshold: f32,
+    frames: Option<u64>,
+    seed: Option<u64>,
+    record: Option<String>,
+    play: Option<String>,
+    start_paused: bool,
+    debug: bool,
+    breakpoints: Vec<u16>,
+    gdb_port: Option<u16>,
+    trace: Option<String>,
+    profile: bool,
+    profile_json: Option<String>,
+}
 
 fn main() {
+    if env::args().skip(1).any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}\n\n{}", USAGE, help());
+        return;
+    }
+
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            eprintln!("{}", USAGE);
+            eprintln!("Try chip-8 --help for the options.");
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(&options) {
+        match err {
+            // Compile and settings errors name the file already
+            Chip8Error::Syntax { .. } | Chip8Error::InvalidConfig { .. } => eprintln!("{}", err),
+            _ => eprintln!("{}: {}", options.file_name, err),
+        }
+        process::exit(1);
+    }
+}
 
-    let file_name = env::args().nth(1).expect("Provide a rom as the first argument.");
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> ::std::result::Result<Options, String> {
+    let mut file_name = None;
+    let mut platform = None;
+    let mut preset = None;
+    let mut quirk_overrides = vec![];
+    let mut rom_config = None;
+    let mut scale = spec::DISPLAY_SCALE;
+    let mut ips = None;
+    let mut fullscreen = false;
+    let mut headless = false;
+    let mut palette = None;
+    let mut beep_frequency = spec::BEEP_FREQUENCY;
+    let mut volume = spec::BEEP_VOLUME;
+    let mut keymap = KeyMap::new();
+    let mut keymap_file = None;
+    let mut stick_threshold = gamepad::DEFAULT_THRESHOLD;
+    let mut frames = None;
+    let mut seed = None;
+    let mut record = None;
+    let mut play = None;
+    let mut start_paused = false;
+    let mut debug = false;
+    let mut breakpoints = vec![];
+    let mut gdb_port = None;
+    let mut trace = None;
+    let mut profile = false;
+    let mut profile_json = None;
 
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                let name = args.next().ok_or("--platform needs a platform name")?;
+                platform = Some(Platform::from_name(&name)
+                    .ok_or_else(|| format!("Unknown platform: {}", name))?);
+            }
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a preset name")?;
+                preset = Some(Quirks::preset(&name)
+                    .ok_or_else(|| format!("Unknown quirks preset: {}", name))?);
+            }
+            "--quirk" => {
+                let setting = args.next().ok_or("--quirk needs a <name>=<on|off> setting")?;
+                let mut parts = setting.splitn(2, '=');
+                let name = parts.next().unwrap_or_default();
+                let value = match parts.next() {
+                    Some("on") | None => true,
+                    Some("off") => false,
+                    Some(value) => return Err(format!("Invalid quirk value: {}", value)),
+                };
+                if !quirks::QUIRK_NAMES.contains(&name) {
+                    return Err(format!("Unknown quirk: {}", name));
+                }
+                quirk_overrides.push((name.to_string(), value));
+            }
+            "--rom-config" => rom_config = Some(args.next().ok_or("--rom-config needs a file")?),
+            "--scale" => {
+                let value = args.next().ok_or("--scale needs a number")?;
+                scale = value.parse()
+                    .ok()
+                    .filter(|&scale| scale > 0)
+                    .ok_or_else(|| format!("Invalid scale: {}", value))?;
+            }
+            "--ips" => {
+                let value = args.next().ok_or("--ips needs a number")?;
+                ips = Some(value.parse()
+                    .ok()
+                    .filter(|&ips| ips > 0)
+                    .ok_or_else(|| format!("Invalid instructions per second: {}", value))?);
+            }
+            "--fullscreen" => fullscreen = true,
+            "--headless" => headless = true,
+            "--palette" => {
+                let colors = args.next().ok_or("--palette needs a list of colours")?;
+                palette = Some(parse_palette(&colors)?);
+            }
+            "--beep" => {
+                let value = args.next().ok_or("--beep needs a frequency")?;
+                beep_frequency = value.parse()
+                    .ok()
+                    .filter(|&hz: &f32| hz > 0.0 && hz < spec::AUDIO_SAMPLE_RATE as f32 / 2.0)
+                    .ok_or_else(|| format!("Invalid beep frequency: {}", value))?;
+            }
+            "--volume" => {
+                let value = args.next().ok_or("--volume needs a percentage")?;
+                volume = value.parse::<u8>()
+                    .ok()
+                    .filter(|&percent| percent <= 100)
+                    .ok_or_else(|| format!("Invalid volume: {}", value))? as f32 / 100.0;
+            }
+            "--keymap" => {
+                let layout = args.next().ok_or("--keymap needs 16 keys")?;
+                let layout = KeyMap::from_layout(&layout)
+                    .filter(|layout| check_keys(layout).is_ok())
+                    .ok_or_else(|| format!("Invalid key map: {}", layout))?;
+                keymap.extend(&layout);
+            }
+            "--bind" => {
+                let binding = args.next().ok_or("--bind needs a <key>=<host keys> binding")?;
+                let (key, names) = KeyMap::parse_binding(&binding)
+                    .ok_or_else(|| format!("Invalid key binding: {}", binding))?;
+                keymap.bind(key, names);
+                check_keys(&keymap).map_err(|err| format!("{} in {}", err, binding))?;
+            }
+            "--keymap-file" => keymap_file = Some(args.next().ok_or("--keymap-file needs a file")?),
+            "--stick-threshold" => {
+                let value = args.next().ok_or("--stick-threshold needs a percentage")?;
+                stick_threshold = value.parse::<u8>()
+                    .ok()
+                    .filter(|&percent| percent > 0 && percent <= 100)
+                    .ok_or_else(|| format!("Invalid stick threshold: {}", value))? as f32 / 100.0;
+            }
+            "--frames" => {
+                let value = args.next().ok_or("--frames needs a number")?;
+                frames = Some(value.parse().map_err(|_| format!("Invalid frame count: {}", value))?);
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a number")?;
+                seed = Some(value.parse().map_err(|_| format!("Invalid seed: {}", value))?);
+            }
+            "--record" => record = Some(args.next().ok_or("--record needs a movie file")?),
+            "--play" => play = Some(args.next().ok_or("--play needs a movie file")?),
+            "--start-paused" => start_paused = true,
+            "--debug" => debug = true,
+            "--break" => {
+                let addr = args.next().ok_or("--break needs an address")?;
+                breakpoints.push(u16::from_str_radix(addr.trim_start_matches("0x"), 16)
+                    .map_err(|_| format!("Invalid address: {}", addr))?);
+            }
+            "--gdb" => {
+                let port = args.next().ok_or("--gdb needs a port")?;
+                gdb_port = Some(port.parse().map_err(|_| format!("Invalid port: {}", port))?);
+            }
+            "--trace" => trace = Some(args.next().ok_or("--trace needs a file")?),
+            "--profile" => profile = true,
+            "--profile-json" => profile_json = Some(args.next().ok_or("--profile-json needs a file")?),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            _ if file_name.is_some() => return Err(format!("Unexpected argument: {}", arg)),
+            _ => file_name = Some(arg),
+        }
+    }
+
+    if record.is_some() && play.is_some() {
+        return Err("--record and --play can't be used together".to_string());
+    }
+
+    Ok(Options {
+        file_name: file_name.ok_or("Provide a rom as the first argument.")?,
+        platform,
+        preset,
+        quirk_overrides,
+        rom_config,
+        scale,
+        ips,
+        fullscreen,
+        headless,
+        palette,
+        beep_frequency,
+        volume,
+        keymap,
+        keymap_file,
+        stick_threshold,
+        frames,
+        seed,
+        record,
+        play,
+        start_paused,
+        debug,
+        breakpoints,
+        gdb_port,
+        trace,
+        profile,
+        profile_json,
+    })
+}
+
+fn run(options: &Options) -> Result<()> {
     // Read rom file
-    println!("Reading from {}", file_name);
-    let file = File::open(file_name).unwrap();
+    println!("Reading from {}", options.file_name);
+    let mut rom = Vec::new();
+    File::open(&options.file_name)?.read_to_end(&mut rom)?;
+    if options.file_name.ends_with(".8o") {
+        rom = octo::compile(&String::from_utf8_lossy(&rom), &options.file_name)?;
+    }
+
+    // Settings given on the command line win over the rom's own
+    let hash = movie::rom_hash(&rom);
+    println!("SHA-1: {}", hash);
+    let overrides = match options.rom_config {
+        Some(ref path) => Some(PathBuf::from(path)),
+        None => romdb::user_config_path().filter(|path| path.exists()),
+    };
+    let config = RomConfig::lookup(&hash, overrides.as_deref())?;
+    if let Some(ref title) = config.title {
+        println!("Rom: {}", title);
+    }
+
+    let platform = options.platform.or(config.platform).unwrap_or_default();
+    // A platform from the command line brings its own quirks
+    let mut quirks = match (options.preset, options.platform) {
+        (Some(preset), _) => preset,
+        (None, Some(platform)) => platform.quirks(),
+        (None, None) => config.quirks.unwrap_or_else(|| platform.quirks()),
+    };
+    for &(ref name, value) in options.quirk_overrides.iter() {
+        quirks.set(name, value);
+    }
+    let ips = options.ips.or(config.ips).unwrap_or(spec::DEFAULT_IPS);
+
+    // A movie replays with the seed it was recorded with
+    let movie = match options.play {
+        Some(ref path) => {
+            let movie = Movie::read(&mut BufReader::new(File::open(path)?))?;
+            if !movie.matches(&rom) {
+                eprintln!("Warning: {} was recorded with a different rom", path);
+            }
+            movie.check_machine(platform, quirks)?;
+            Some(movie)
+        }
+        None => None,
+    };
+    let rng = match (&movie, options.seed) {
+        (Some(movie), _) => Rng::new(movie.seed),
+        (None, Some(seed)) => Rng::new(seed),
+        (None, None) => Rng::from_entropy(),
+    };
+
+    // Initialize VM
+    let mut machine = Machine::with_platform(platform);
+    machine.set_quirks(quirks);
+    println!("Seed: {}", rng.seed());
+    machine.set_rng(rng.clone());
+    machine.load_rom(&rom)?;
+
+    if options.headless {
+        let mut cpu = Cpu::new(machine, NullVideo, NullInput, NullAudio);
+        cpu.set_ips(ips);
+        return emulate(cpu, options, &rom, movie, &rng);
+    }
 
     // Initialize SDL
     let sdl_context = sdl2::init().unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
 
-    // Initialize VM
-    let mut cpu = cpu::Cpu::new(&sdl_context, &file);
-    println!("Initial state: {}", cpu);
+    let mut video = SdlVideo::new(&sdl_context, options.scale, options.fullscreen);
+    if let Some(palette) = options.palette.or(config.palette) {
+        video.set_palette(palette);
+    }
 
-    while cpu.is_running() {
-        cpu.tick(&mut event_pump);
+    // Bindings apply key by key over the ones before: the user's, the rom's,
+    // then the command line's
+    let mut bindings = KeyMap::new();
+    let keymap_file = match options.keymap_file {
+        Some(ref path) => Some(PathBuf::from(path)),
+        None => keymap::user_keymap_path().filter(|path| path.exists()),
+    };
+    if let Some(ref path) = keymap_file {
+        let user = KeyMap::read(path)?;
+        check_keys(&user).map_err(|reason| Chip8Error::InvalidConfig { file: path.display().to_string(), reason })?;
+        bindings.extend(&user);
+    }
+    if let Some(ref rom) = config.keymap {
+        let file = overrides.as_ref().map(|path| path.display().to_string()).unwrap_or_default();
+        check_keys(rom).map_err(|reason| Chip8Error::InvalidConfig { file, reason })?;
+        bindings.extend(rom);
+    }
+    bindings.extend(&options.keymap);
+    let invalid = |reason| Chip8Error::InvalidConfig { file: "key bindings".to_string(), reason };
+    let keyboard = Keyboard::with_map(&bindings).map_err(invalid)?;
+    let mut gamepad = Gamepad::with_map(&bindings).map_err(invalid)?;
+    gamepad.set_threshold(options.stick_threshold);
+    let input = SdlInput::new(&sdl_context, keyboard, gamepad);
+    let audio = SdlAudio::new(&sdl_context, options.beep_frequency, options.volume);
+    let mut cpu = Cpu::new(machine, video, input, audio);
+    cpu.set_ips(ips);
+    emulate(cpu, options, &rom, movie, &rng)
+}
+
+/// Runs the emulator until it is closed, the rom exits or fails, or the
+/// frame limit is reached
+fn emulate<V, I, A>(mut cpu: Cpu<V, I, A>, options: &Options, rom: &[u8], movie: Option<Movie>, rng: &Rng) -> Result<()>
+    where V: VideoSink,
+          I: InputSource,
+          A: AudioSink
+{
+    cpu.set_state_path(format!("{}.state", options.file_name));
+    for &addr in options.breakpoints.iter() {
+        cpu.debugger().add_breakpoint(addr);
+    }
+    cpu.set_paused(options.start_paused);
+    if options.debug {
+        cpu.set_debug(true);
     }
+
+    if let Some(port) = options.gdb_port {
+        println!("Waiting for GDB on port {}", port);
+        cpu.attach_gdb(GdbStub::listen(("127.0.0.1", port))?);
+    }
+
+    if let Some(ref path) = options.trace {
+        cpu.set_tracer(Tracer::create(path)?);
+    }
+    if options.profile || options.profile_json.is_some() {
+        cpu.set_profiler(Profiler::new());
+    }
+
+    if let Some(movie) = movie {
+        cpu.play(movie);
+    } else if options.record.is_some() {
+        cpu.record(Movie::new(rom, rng.seed(), cpu.get_ips(), cpu.machine().platform(), cpu.machine().quirks()));
+    }
+
+    let mut result = Ok(());
+    while cpu.is_running() && result.is_ok() {
+        result = cpu.tick();
+        if options.frames.is_some_and(|frames| cpu.frame() >= frames) {
+            break;
+        }
+    }
+
+    if let Some(mut tracer) = cpu.finish_trace() {
+        tracer.flush()?;
+    }
+
+    if let Some(profiler) = cpu.finish_profile() {
+        if options.profile {
+            profiler.write_report(cpu.machine(), &mut io::stdout())?;
+        }
+        if let Some(ref path) = options.profile_json {
+            profiler.write_json(cpu.machine(), &mut BufWriter::new(File::create(path)?))?;
+            println!("Wrote profile to {}", path);
+        }
+    }
+
+    // Keep the movie even if the rom crashed; it is the way to reproduce it
+    if let (Some(path), Some(movie)) = (options.record.as_ref(), cpu.finish_recording()) {
+        movie.write(&mut File::create(path)?)?;
+        println!("Recorded movie to {}", path);
+    }
+
+    result
+}
+
+/// Parses comma separated 0xRRGGBB colours, for the background and then the
+/// pixels of each plane mask. The colours left out keep their defaults.
+fn parse_palette(colors: &str) -> ::std::result::Result<[u32; 16], String> {
+    let mut palette = spec::DEFAULT_PALETTE;
+    let colors: Vec<&str> = colors.split(',').collect();
+    if colors.len() > palette.len() {
+        return Err(format!("A palette has at most {} colours", palette.len()));
+    }
+
+    for (color, value) in palette.iter_mut().zip(colors) {
+        *color = romdb::parse_color(value).ok_or_else(|| format!("Invalid colour: {}", value))?;
+    }
+    Ok(palette)
+}
+
+/// Checks that bindings name known keys and buttons
+fn check_keys(map: &KeyMap) -> ::std::result::Result<(), String> {
+    Keyboard::with_map(map)?;
+    Gamepad::with_map(map)?;
+    Ok(())
+}
+
+fn help() -> String {
+    format!("\
+Display and speed:
+  --scale <n>              host pixels per low resolution pixel (default {scale})
+  --fullscreen             cover the whole desktop
+  --palette <colours>      comma separated 0xRRGGBB colours, for the
+                           background and then plane masks 1 to F
+  --ips <n>                instructions per second (default {ips})
+  --headless               run without a window, sound or input
+  --frames <n>             stop after n frames
+
+Sound:
+  --beep <hz>              pitch of the beep (default {beep})
+  --volume <n>             percentage of the full volume (default {volume})
+
+Compatibility:
+  --platform <name>        the machine to emulate: {platforms}
+                           (default chip8)
+  --quirks <preset>        quirks preset: {presets}
+                           (default: the platform's, vip for chip8)
+  --quirk <name>=<on|off>  set a single quirk, one of
+                           {quirks}
+  --rom-config <file>      rom settings to use over the bundled ones
+                           (default ~/.config/chip-8/roms.toml)
+
+Input:
+  --keymap <keys>          the 16 host keys of the hex keys 0 to F
+                           (default: 1234/QWER/ASDF/ZXCV by position)
+  --bind <key>=<host keys> bind comma separated host keys to a hex key
+  --keymap-file <file>     key bindings to use over the default ones
+                           (default ~/.config/chip-8/keymap.toml)
+  --stick-threshold <n>    percentage of their travel sticks and triggers
+                           must be pushed to press (default 50)
+  --record <movie>         record the keypad to a movie file
+  --play <movie>           replay a movie in place of the keyboard
+
+Randomness:
+  --seed <n>               seed the random number generator, to repeat a run
+
+Debugging:
+  --start-paused           start paused; Enter pauses and resumes
+  --debug                  start stopped in the debugger; P stops and resumes
+  --break <addr>           stop in the debugger at addr
+  --gdb <port>             wait for a GDB remote protocol client
+  --trace <file>           write a JSON line per instruction executed
+  --profile                print an execution profile on exit
+  --profile-json <file>    write the execution profile as JSON
+  -h, --help               show this help",
+            scale = spec::DISPLAY_SCALE,
+            ips = spec::DEFAULT_IPS,
+            beep = spec::BEEP_FREQUENCY,
+            volume = spec::BEEP_VOLUME * 100.0,
+            platforms = platform::PLATFORM_NAMES.join("|"),
+            presets = quirks::PRESET_NAMES.join("|"),
+            quirks = quirks::QUIRK_NAMES.join(", "))
 }
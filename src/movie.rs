@@ -0,0 +1,232 @@
+//! Input movies.
+//!
+//! A movie is every keypad state change of a run, tagged with the frame and
+//! the instruction count it happened at, along with what is needed to replay
+//! the run exactly: the hash of the rom, the random seed, the clock speed, the
+//! platform and the quirks. `none` stands for no quirks at all.
+//!
+//! Movies are stored as text, one item per line:
+//!
+//! ```text
+//! chip-8 movie 1
+//! rom 2d3f5a...
+//! seed 1234
+//! ips 700
+//! platform chip8
+//! quirks shift-vy,load-store-i,clip,vf-reset,display-wait
+//! key 120 8400 5 down
+//! key 131 9170 5 up
+//! ```
+
+use std::io::{BufRead, Write};
+
+use sha1_smol::Sha1;
+
+use error::{Chip8Error, Result};
+use platform::Platform;
+use quirks::Quirks;
+
+const HEADER: &str = "chip-8 movie 1";
+
+/// A keypad state change
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// Frames run before the change
+    pub frame: u64,
+    /// Instructions executed before the change
+    pub instruction: u64,
+    pub key: usize,
+    pub down: bool,
+}
+
+/// A recorded run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: String,
+    pub seed: u64,
+    pub ips: u32,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub events: Vec<KeyEvent>,
+}
+
+impl Movie {
+    /// An empty movie of the given rom
+    pub fn new(rom: &[u8], seed: u64, ips: u32, platform: Platform, quirks: Quirks) -> Movie {
+        Movie {
+            rom_hash: rom_hash(rom),
+            seed,
+            ips,
+            platform,
+            quirks,
+            events: vec![],
+        }
+    }
+
+    /// Writes the movie in its text format
+    pub fn write(&self, w: &mut dyn Write) -> Result<()> {
+        writeln!(w, "{}", HEADER)?;
+        writeln!(w, "rom {}", self.rom_hash)?;
+        writeln!(w, "seed {}", self.seed)?;
+        writeln!(w, "ips {}", self.ips)?;
+        writeln!(w, "platform {}", self.platform.name())?;
+        let quirks = self.quirks.enabled();
+        writeln!(w, "quirks {}", if quirks.is_empty() { "none".to_string() } else { quirks.join(",") })?;
+
+        for event in self.events.iter() {
+            writeln!(w,
+                     "key {} {} {:x} {}",
+                     event.frame,
+                     event.instruction,
+                     event.key,
+                     if event.down { "down" } else { "up" })?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a movie written by `write`
+    pub fn read(r: &mut dyn BufRead) -> Result<Movie> {
+        let mut movie = Movie {
+            rom_hash: String::new(),
+            seed: 0,
+            ips: 0,
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            events: vec![],
+        };
+        let mut platform = None;
+        let mut quirks = None;
+
+        for (index, line) in r.lines().enumerate() {
+            let line = line?;
+            let number = index + 1;
+            let invalid = |reason| Chip8Error::InvalidMovie { line: number, reason };
+
+            if index == 0 {
+                if line.trim() != HEADER {
+                    return Err(invalid("not a movie"));
+                }
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => {}
+                ["rom", hash] => movie.rom_hash = hash.to_string(),
+                ["seed", seed] => movie.seed = seed.parse().map_err(|_| invalid("invalid seed"))?,
+                ["ips", ips] => movie.ips = ips.parse().map_err(|_| invalid("invalid clock speed"))?,
+                ["platform", name] => {
+                    platform = Some(Platform::from_name(name).ok_or_else(|| invalid("unknown platform"))?);
+                }
+                ["quirks", "none"] => quirks = Some(Quirks::default()),
+                ["quirks", names] => {
+                    let mut enabled = Quirks::default();
+                    for name in names.split(',') {
+                        if !enabled.set(name, true) {
+                            return Err(invalid("unknown quirk"));
+                        }
+                    }
+                    quirks = Some(enabled);
+                }
+                ["key", frame, instruction, key, state] => {
+                    let key = usize::from_str_radix(key, 16)
+                        .ok()
+                        .filter(|&key| key < 16)
+                        .ok_or_else(|| invalid("invalid key"))?;
+                    let down = match *state {
+                        "down" => true,
+                        "up" => false,
+                        _ => return Err(invalid("invalid key state")),
+                    };
+
+                    movie.events.push(KeyEvent {
+                        frame: frame.parse().map_err(|_| invalid("invalid frame"))?,
+                        instruction: instruction.parse()
+                            .map_err(|_| invalid("invalid instruction count"))?,
+                        key,
+                        down,
+                    });
+                }
+                _ => return Err(invalid("unknown line")),
+            }
+        }
+
+        if movie.rom_hash.is_empty() {
+            return Err(Chip8Error::InvalidMovie { line: 0, reason: "missing rom hash" });
+        }
+        movie.platform = platform.ok_or(Chip8Error::InvalidMovie { line: 0, reason: "missing platform" })?;
+        movie.quirks = quirks.ok_or(Chip8Error::InvalidMovie { line: 0, reason: "missing quirks" })?;
+
+        Ok(movie)
+    }
+
+    /// Whether the movie was recorded with the given rom
+    pub fn matches(&self, rom: &[u8]) -> bool {
+        self.rom_hash == rom_hash(rom)
+    }
+
+    /// Fails unless the movie was recorded on platform with quirks; replaying
+    /// it on another machine would go out of sync
+    pub fn check_machine(&self, platform: Platform, quirks: Quirks) -> Result<()> {
+        if self.platform != platform {
+            return Err(Chip8Error::InvalidMovie { line: 0, reason: "recorded on another platform" });
+        }
+        if self.quirks != quirks {
+            return Err(Chip8Error::InvalidMovie { line: 0, reason: "recorded with other quirks" });
+        }
+        Ok(())
+    }
+}
+
+/// Hex encoded SHA-1 hash of a rom
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::from(rom).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Result<Movie> {
+        Movie::read(&mut text.as_bytes())
+    }
+
+    #[test]
+    fn write_and_read_round_trip() {
+        for &quirks in [Quirks::vip(), Quirks::default()].iter() {
+            let mut movie = Movie::new(&[0x12, 0x00], 1234, 700, Platform::Schip, quirks);
+            movie.events.push(KeyEvent { frame: 120, instruction: 8400, key: 0xa, down: true });
+            movie.events.push(KeyEvent { frame: 131, instruction: 9170, key: 0xa, down: false });
+
+            let mut text = vec![];
+            movie.write(&mut text).unwrap();
+            assert_eq!(Movie::read(&mut &text[..]).unwrap(), movie);
+        }
+    }
+
+    #[test]
+    fn playback_needs_the_same_machine() {
+        let movie = Movie::new(&[], 0, 700, Platform::Chip8, Quirks::vip());
+        assert!(movie.check_machine(Platform::Chip8, Quirks::vip()).is_ok());
+        assert!(movie.check_machine(Platform::Schip, Quirks::vip()).is_err());
+        assert!(movie.check_machine(Platform::Chip8, Quirks::chip48()).is_err());
+    }
+
+    #[test]
+    fn platform_and_quirks_are_required() {
+        let movie = "chip-8 movie 1\nrom 00\nseed 1\nips 700\n";
+        match read(&format!("{}quirks none\n", movie)) {
+            Err(Chip8Error::InvalidMovie { reason: "missing platform", .. }) => {}
+            other => panic!("read {:?}", other.map_err(|err| err.to_string())),
+        }
+        match read(&format!("{}platform chip8\n", movie)) {
+            Err(Chip8Error::InvalidMovie { reason: "missing quirks", .. }) => {}
+            other => panic!("read {:?}", other.map_err(|err| err.to_string())),
+        }
+        match read(&format!("{}platform chip8\nquirks clip,warp\n", movie)) {
+            Err(Chip8Error::InvalidMovie { line: 6, reason: "unknown quirk" }) => {}
+            other => panic!("read {:?}", other.map_err(|err| err.to_string())),
+        }
+    }
+}
@@ -0,0 +1,902 @@
+//! Compiler for the Octo language.
+//!
+//! Covers the statements of Octo programs: labels, the `:=`/`+=`/... register
+//! operations, `if ... then`, `if ... begin ... else ... end`,
+//! `loop ... while ... again`, `:const`, `:alias`, `:macro`, `:calc`,
+//! `:byte`, `:org` and `:unpack`. Programs start at their `main` label, with
+//! a jump to it at `PROGRAM_START` unless it comes first.
+//!
+//! As in Octo, `:calc` expressions have no operator precedence and are
+//! evaluated right to left, and every token is separated by whitespace.
+
+use std::collections::{HashMap, VecDeque};
+use std::result;
+
+use error::{Chip8Error, Result};
+use instr::Opcode;
+use spec;
+
+/// Deepest nesting of macro invocations, to catch macros invoking themselves
+const MAX_MACRO_DEPTH: usize = 64;
+
+type CompileResult<T> = result::Result<T, String>;
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+    /// Nesting of the macro invocation the token comes from
+    depth: usize,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// Where a label goes in the code
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    /// The low 12 bits of an instruction
+    Address,
+    /// A whole word, as in `i := long`
+    Long,
+    /// The two `vx := byte` of `:unpack`
+    Unpack,
+}
+
+/// A reference to a label not defined yet
+struct Fixup {
+    offset: usize,
+    name: String,
+    line: usize,
+    slot: Slot,
+}
+
+/// The test of an `if` or a `while`: the instructions that prepare it, then
+/// the skips taken when it is false or true
+struct Condition {
+    setup: Vec<Opcode>,
+    skip_false: Opcode,
+    skip_true: Opcode,
+}
+
+/// Compiles Octo source into a rom loaded at `PROGRAM_START`. Errors name the
+/// source `name` and the line they are on.
+pub fn compile(source: &str, name: &str) -> Result<Vec<u8>> {
+    let tokens = tokenize(source);
+    let has_main = tokens.windows(2).any(|pair| pair[0].text == ":" && pair[1].text == "main");
+
+    let compiler = Compiler::run(tokens.clone(), has_main, name)?;
+    // No need to jump to main if it comes right after the jump
+    if compiler.labels.get("main") == Some(&(spec::PROGRAM_START as u16 + 2)) {
+        return Compiler::run(tokens, false, name).map(|compiler| compiler.rom);
+    }
+
+    Ok(compiler.rom)
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or_default();
+        for text in code.split_whitespace() {
+            tokens.push(Token {
+                text: text.to_string(),
+                line: index + 1,
+                depth: 0,
+            });
+        }
+    }
+
+    tokens
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    /// Line of the last token read
+    line: usize,
+    rom: Vec<u8>,
+    /// Where the next byte goes, as an offset in the rom
+    here: usize,
+
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, usize>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+
+    /// Start address and pending `while` jumps of the open loops
+    loops: Vec<(u16, Vec<usize>)>,
+    /// Pending jumps of the open `begin` blocks
+    branches: Vec<usize>,
+}
+
+impl Compiler {
+    fn run(tokens: Vec<Token>, jump_to_main: bool, name: &str) -> Result<Compiler> {
+        let mut compiler = Compiler {
+            tokens: tokens.into_iter().collect(),
+            line: 0,
+            rom: vec![],
+            here: 0,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: vec![],
+            loops: vec![],
+            branches: vec![],
+        };
+
+        let error = |line, reason| Chip8Error::Syntax { file: name.to_string(), line, reason };
+
+        if jump_to_main {
+            compiler.fixups.push(Fixup { offset: 0, name: "main".to_string(), line: 0, slot: Slot::Address });
+            compiler.emit(Opcode::Jp { nnn: 0 });
+        }
+
+        while !compiler.tokens.is_empty() {
+            compiler.statement().map_err(|reason| error(compiler.line, reason))?;
+        }
+
+        if !compiler.loops.is_empty() {
+            return Err(error(compiler.line, "loop without again".to_string()));
+        }
+        if !compiler.branches.is_empty() {
+            return Err(error(compiler.line, "begin without end".to_string()));
+        }
+
+        for fixup in compiler.fixups.iter() {
+            let addr = match compiler.labels.get(&fixup.name) {
+                Some(&addr) => addr,
+                None => return Err(error(fixup.line, format!("undefined label: {}", fixup.name))),
+            };
+            let rom = &mut compiler.rom;
+            match fixup.slot {
+                Slot::Address => patch(rom, fixup.offset, addr).map_err(|reason| error(fixup.line, reason))?,
+                Slot::Long => rom[fixup.offset..(fixup.offset + 2)].copy_from_slice(&addr.to_be_bytes()),
+                Slot::Unpack => {
+                    let addr = to_address(addr as i64, Slot::Unpack).map_err(|reason| error(fixup.line, reason))?;
+                    rom[fixup.offset + 1] |= (addr >> 8) as u8;
+                    rom[fixup.offset + 3] = addr as u8;
+                }
+            }
+        }
+
+        Ok(compiler)
+    }
+
+    fn statement(&mut self) -> CompileResult<()> {
+        let token = self.next()?;
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.insert(name.clone(), self.address()).is_some() {
+                    return Err(format!("{} is already defined", name));
+                }
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+            }
+            ":macro" => self.define_macro()?,
+            ":byte" => {
+                let value = if self.peek() == Some("{") { self.calc()? } else { self.value()? };
+                self.emit_byte(value)?;
+            }
+            ":org" => {
+                let addr = self.value()? as usize;
+                self.here = addr.checked_sub(spec::PROGRAM_START)
+                    .ok_or_else(|| format!("cannot place code at {:x}", addr))?;
+            }
+            ":unpack" => {
+                // v0 gets the nibble and the high bits of the address, v1 the low byte
+                let nibble = self.nibble()?;
+                let addr = self.target(Slot::Unpack)?;
+                self.emit(Opcode::Ld { x: 0, kk: nibble << 4 | (addr >> 8) as u8 });
+                self.emit(Opcode::Ld { x: 1, kk: addr as u8 });
+            }
+            ":call" => {
+                let nnn = self.target(Slot::Address)?;
+                self.emit(Opcode::Call { nnn });
+            }
+            ":breakpoint" | ":proto" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ";" | "return" => self.emit(Opcode::Ret),
+            "clear" => self.emit(Opcode::Cls),
+            "hires" => self.emit(Opcode::HighRes),
+            "lores" => self.emit(Opcode::LowRes),
+            "scroll-left" => self.emit(Opcode::ScrollLeft),
+            "scroll-right" => self.emit(Opcode::ScrollRight),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(Opcode::ScrollDown { n });
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(Opcode::ScrollUp { n });
+            }
+            "exit" => self.emit(Opcode::Exit),
+            "audio" => self.emit(Opcode::Audio),
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(Opcode::Plane { n });
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Opcode::LdBCD { x });
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let save = token.text == "save";
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    self.emit(if save { Opcode::SaveRange { x, y } } else { Opcode::LoadRange { x, y } });
+                } else {
+                    self.emit(if save { Opcode::SaveRegs { x } } else { Opcode::RestoreRegs { x } });
+                }
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(Opcode::SaveRpl { x });
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(Opcode::LoadRpl { x });
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(Opcode::Drw { x, y, n });
+            }
+            "jump" => {
+                let nnn = self.target(Slot::Address)?;
+                self.emit(Opcode::Jp { nnn });
+            }
+            "jump0" => {
+                let nnn = self.target(Slot::Address)?;
+                self.emit(Opcode::JpV0 { nnn });
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(match token.text.as_str() {
+                    "delay" => Opcode::LdDt { x },
+                    "buzzer" => Opcode::LdSt { x },
+                    _ => Opcode::Pitch { x },
+                });
+            }
+            "i" => self.assign_i()?,
+            "loop" => self.loops.push((self.address(), vec![])),
+            "while" => {
+                let condition = self.condition()?;
+                for opcode in condition.setup {
+                    self.emit(opcode);
+                }
+                self.emit(condition.skip_true);
+                let offset = self.here;
+                self.emit(Opcode::Jp { nnn: 0 });
+                self.loops.last_mut().ok_or("while outside of a loop")?.1.push(offset);
+            }
+            "again" => {
+                let (start, exits) = self.loops.pop().ok_or("again without loop")?;
+                self.emit(Opcode::Jp { nnn: start });
+                let addr = self.address();
+                for offset in exits {
+                    patch(&mut self.rom, offset, addr)?;
+                }
+            }
+            "if" => {
+                let condition = self.condition()?;
+                for opcode in condition.setup {
+                    self.emit(opcode);
+                }
+                match self.next()?.text.as_str() {
+                    "then" => self.emit(condition.skip_false),
+                    "begin" => {
+                        self.emit(condition.skip_true);
+                        self.branches.push(self.here);
+                        self.emit(Opcode::Jp { nnn: 0 });
+                    }
+                    other => return Err(format!("expected then or begin, found {}", other)),
+                }
+            }
+            "else" => {
+                let offset = self.branches.pop().ok_or("else without if")?;
+                self.branches.push(self.here);
+                self.emit(Opcode::Jp { nnn: 0 });
+                let addr = self.address();
+                patch(&mut self.rom, offset, addr)?;
+            }
+            "end" => {
+                let offset = self.branches.pop().ok_or("end without if")?;
+                let addr = self.address();
+                patch(&mut self.rom, offset, addr)?;
+            }
+            text => {
+                if let Some(x) = self.register_named(text) {
+                    self.assign(x)?;
+                } else if self.macros.contains_key(text) {
+                    self.expand(&token)?;
+                } else if let Some(value) = self.number(text) {
+                    self.emit_byte(value)?;
+                } else if is_name(text) {
+                    // A label on its own calls it
+                    let nnn = self.label(text, Slot::Address)?;
+                    self.emit(Opcode::Call { nnn });
+                } else {
+                    return Err(format!("unexpected {}", text));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `vx := ...` and the other register operations
+    fn assign(&mut self, x: usize) -> CompileResult<()> {
+        let operator = self.next()?.text;
+        let source = self.peek().and_then(|text| self.register_named(text));
+        if source.is_some() {
+            self.next()?;
+        }
+
+        let opcode = match (operator.as_str(), source) {
+            (":=", Some(y)) => Opcode::LdReg { x, y },
+            (":=", None) => {
+                match self.peek() {
+                    Some("random") => {
+                        self.next()?;
+                        Opcode::Rnd { x, kk: self.byte()? }
+                    }
+                    Some("key") => {
+                        self.next()?;
+                        Opcode::LdVxK { x }
+                    }
+                    Some("delay") => {
+                        self.next()?;
+                        Opcode::LdVxDt { x }
+                    }
+                    _ => Opcode::Ld { x, kk: self.byte()? },
+                }
+            }
+            ("+=", Some(y)) => Opcode::AddV { x, y },
+            ("+=", None) => Opcode::AddB { x, kk: self.byte()? },
+            ("-=", Some(y)) => Opcode::Sub { x, y },
+            ("-=", None) => Opcode::AddB { x, kk: self.byte()?.wrapping_neg() },
+            ("=-", Some(y)) => Opcode::SubN { x, y },
+            ("|=", Some(y)) => Opcode::Or { x, y },
+            ("&=", Some(y)) => Opcode::And { x, y },
+            ("^=", Some(y)) => Opcode::Xor { x, y },
+            (">>=", Some(y)) => Opcode::Shr { x, y },
+            ("<<=", Some(y)) => Opcode::Shl { x, y },
+            (operator, _) => return Err(format!("invalid register operation: {}", operator)),
+        };
+
+        self.emit(opcode);
+        Ok(())
+    }
+
+    /// `i := ...` and `i += vx`
+    fn assign_i(&mut self) -> CompileResult<()> {
+        let operator = self.next()?.text;
+
+        if operator == "+=" {
+            let x = self.register()?;
+            self.emit(Opcode::AddI { x });
+            return Ok(());
+        }
+        if operator != ":=" {
+            return Err(format!("invalid operation on i: {}", operator));
+        }
+
+        match self.peek() {
+            Some("hex") => {
+                self.next()?;
+                let x = self.register()?;
+                self.emit(Opcode::LdSprite { x });
+            }
+            Some("bighex") => {
+                self.next()?;
+                let x = self.register()?;
+                self.emit(Opcode::LdBigSprite { x });
+            }
+            Some("long") => {
+                self.next()?;
+                self.emit(Opcode::LdILong);
+                let addr = self.target(Slot::Long)?;
+                self.emit_word(addr);
+            }
+            _ => {
+                let nnn = self.target(Slot::Address)?;
+                self.emit(Opcode::LdI { nnn });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses the test of an `if` or a `while`
+    fn condition(&mut self) -> CompileResult<Condition> {
+        let x = self.register()?;
+        let operator = self.next()?.text;
+
+        let condition = match operator.as_str() {
+            "key" | "-key" => {
+                let (pressed, released) = (Opcode::SkpVx { x }, Opcode::SknpVx { x });
+                let (skip_false, skip_true) = if operator == "key" { (released, pressed) } else { (pressed, released) };
+                Condition { setup: vec![], skip_false, skip_true }
+            }
+            "==" | "!=" => {
+                let (equal, different) = match self.peek().and_then(|text| self.register_named(text)) {
+                    Some(y) => {
+                        self.next()?;
+                        (Opcode::SeV { x, y }, Opcode::SneV { x, y })
+                    }
+                    None => {
+                        let kk = self.byte()?;
+                        (Opcode::SeB { x, kk }, Opcode::Sne { x, kk })
+                    }
+                };
+                let (skip_false, skip_true) = if operator == "==" { (different, equal) } else { (equal, different) };
+                Condition { setup: vec![], skip_false, skip_true }
+            }
+            "<" | ">" | "<=" | ">=" => {
+                // VF is loaded with the right operand and subtracted, leaving
+                // the borrow flag in it
+                let load = match self.peek().and_then(|text| self.register_named(text)) {
+                    Some(y) => {
+                        self.next()?;
+                        Opcode::LdReg { x: 0xf, y }
+                    }
+                    None => Opcode::Ld { x: 0xf, kk: self.byte()? },
+                };
+                let (subtract, flag_if_true) = match operator.as_str() {
+                    // VF = x - VF: 1 if x >= VF
+                    ">=" => (Opcode::SubN { x: 0xf, y: x }, 1),
+                    "<" => (Opcode::SubN { x: 0xf, y: x }, 0),
+                    // VF = VF - x: 1 if VF >= x
+                    "<=" => (Opcode::Sub { x: 0xf, y: x }, 1),
+                    _ => (Opcode::Sub { x: 0xf, y: x }, 0),
+                };
+                Condition {
+                    setup: vec![load, subtract],
+                    skip_false: Opcode::SeB { x: 0xf, kk: 1 - flag_if_true },
+                    skip_true: Opcode::SeB { x: 0xf, kk: flag_if_true },
+                }
+            }
+            _ => return Err(format!("invalid comparison: {}", operator)),
+        };
+
+        Ok(condition)
+    }
+
+    /// `:macro name args... { body }`
+    fn define_macro(&mut self) -> CompileResult<()> {
+        let name = self.name()?;
+        let mut args = vec![];
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            args.push(token.text);
+        }
+
+        let body = self.block()?;
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    /// Replaces a macro invocation with the macro's body
+    fn expand(&mut self, invocation: &Token) -> CompileResult<()> {
+        if invocation.depth == MAX_MACRO_DEPTH {
+            return Err(format!("macros nested too deep at {}", invocation.text));
+        }
+
+        let arg_count = self.macros[&invocation.text].args.len();
+        let mut values = HashMap::new();
+        for index in 0..arg_count {
+            let value = self.next()?;
+            values.insert(self.macros[&invocation.text].args[index].clone(), value.text);
+        }
+
+        let body = &self.macros[&invocation.text].body;
+        for token in body.iter().rev() {
+            let text = values.get(&token.text).cloned().unwrap_or_else(|| token.text.clone());
+            self.tokens.push_front(Token { text, line: token.line, depth: invocation.depth + 1 });
+        }
+
+        Ok(())
+    }
+
+    /// The tokens up to the `}` closing a block, whose `{` was read already
+    fn block(&mut self) -> CompileResult<Vec<Token>> {
+        let mut tokens = vec![];
+        let mut depth = 0;
+
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(tokens),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+
+    /// `{ expression }`
+    fn calc(&mut self) -> CompileResult<f64> {
+        self.expect("{")?;
+        let tokens = self.block()?;
+        let mut rest = &tokens[..];
+        let value = self.expression(&mut rest)?;
+
+        match rest.first() {
+            Some(token) => Err(format!("unexpected {} in expression", token.text)),
+            None => Ok(value),
+        }
+    }
+
+    /// An operand, then optionally an operator and the rest of the
+    /// expression, evaluated first
+    fn expression(&self, tokens: &mut &[Token]) -> CompileResult<f64> {
+        let left = self.term(tokens)?;
+        let operator = match tokens.first() {
+            Some(token) if token.text != ")" => token.text.clone(),
+            _ => return Ok(left),
+        };
+        *tokens = &tokens[1..];
+        let right = self.expression(tokens)?;
+
+        let int = |value: f64| value as i64;
+        let value = match operator.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (int(left) & int(right)) as f64,
+            "|" => (int(left) | int(right)) as f64,
+            "^" => (int(left) ^ int(right)) as f64,
+            "<<" => (int(left) << int(right)) as f64,
+            ">>" => (int(left) >> int(right)) as f64,
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "pow" => left.powf(right),
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(format!("unknown operator: {}", operator)),
+        };
+
+        Ok(value)
+    }
+
+    fn term(&self, tokens: &mut &[Token]) -> CompileResult<f64> {
+        let (token, rest) = tokens.split_first().ok_or("missing value in expression")?;
+        *tokens = rest;
+
+        let value = match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens)?;
+                match tokens.split_first() {
+                    Some((close, rest)) if close.text == ")" => *tokens = rest,
+                    _ => return Err("missing )".to_string()),
+                }
+                value
+            }
+            "-" => -self.term(tokens)?,
+            "~" => !(self.term(tokens)? as i64) as f64,
+            "!" => (self.term(tokens)? == 0.0) as i64 as f64,
+            "abs" => self.term(tokens)?.abs(),
+            "sqrt" => self.term(tokens)?.sqrt(),
+            "sin" => self.term(tokens)?.sin(),
+            "cos" => self.term(tokens)?.cos(),
+            "floor" => self.term(tokens)?.floor(),
+            "ceil" => self.term(tokens)?.ceil(),
+            "log" => self.term(tokens)?.ln(),
+            "exp" => self.term(tokens)?.exp(),
+            "PI" => ::std::f64::consts::PI,
+            "E" => ::std::f64::consts::E,
+            "HERE" => self.address() as f64,
+            text => self.known_value(text)?,
+        };
+
+        Ok(value)
+    }
+
+    fn next(&mut self) -> CompileResult<Token> {
+        let token = self.tokens.pop_front().ok_or("unexpected end of file")?;
+        self.line = token.line;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> CompileResult<()> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(format!("expected {}, found {}", text, token.text));
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> CompileResult<String> {
+        let token = self.next()?;
+        if !is_name(&token.text) {
+            return Err(format!("invalid name: {}", token.text));
+        }
+        Ok(token.text)
+    }
+
+    fn register(&mut self) -> CompileResult<usize> {
+        let token = self.next()?;
+        self.register_named(&token.text).ok_or_else(|| format!("expected a register, found {}", token.text))
+    }
+
+    /// `v0`-`vf`, or an alias of one
+    fn register_named(&self, text: &str) -> Option<usize> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+        match text.strip_prefix('v').or_else(|| text.strip_prefix('V')) {
+            Some(index) if index.len() == 1 => usize::from_str_radix(index, 16).ok(),
+            _ => None,
+        }
+    }
+
+    /// A number literal or a constant
+    fn number(&self, text: &str) -> Option<f64> {
+        if let Some(&value) = self.constants.get(text) {
+            return Some(value);
+        }
+
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok()?
+        } else if let Some(binary) = digits.strip_prefix("0b") {
+            i64::from_str_radix(binary, 2).ok()?
+        } else {
+            digits.parse().ok()?
+        } as f64;
+
+        Some(if negative { -value } else { value })
+    }
+
+    /// A number, constant or label defined so far
+    fn known_value(&self, text: &str) -> CompileResult<f64> {
+        if let Some(value) = self.number(text) {
+            return Ok(value);
+        }
+        match self.labels.get(text) {
+            Some(&addr) => Ok(addr as f64),
+            None => Err(format!("undefined name: {}", text)),
+        }
+    }
+
+    fn value(&mut self) -> CompileResult<f64> {
+        let token = self.next()?;
+        self.known_value(&token.text)
+    }
+
+    fn byte(&mut self) -> CompileResult<u8> {
+        let value = self.value()?;
+        to_byte(value)
+    }
+
+    fn nibble(&mut self) -> CompileResult<u8> {
+        let value = self.value()? as i64;
+        if !(0..=0xf).contains(&value) {
+            return Err(format!("{} does not fit in a nibble", value));
+        }
+        Ok(value as u8)
+    }
+
+    /// An address: a number, a constant or a label, which may be defined
+    /// later
+    fn target(&mut self, slot: Slot) -> CompileResult<u16> {
+        let token = self.next()?;
+        match self.number(&token.text) {
+            Some(value) => to_address(value as i64, slot),
+            None => self.label(&token.text, slot),
+        }
+    }
+
+    /// The address of a label, or a fixup to fill it in once it is defined
+    fn label(&mut self, name: &str, slot: Slot) -> CompileResult<u16> {
+        if let Some(&addr) = self.labels.get(name) {
+            return to_address(addr as i64, slot);
+        }
+        if !is_name(name) {
+            return Err(format!("invalid address: {}", name));
+        }
+
+        // The address goes in what is emitted next
+        self.fixups.push(Fixup {
+            offset: self.here,
+            name: name.to_string(),
+            line: self.line,
+            slot,
+        });
+        Ok(0)
+    }
+
+    fn address(&self) -> u16 {
+        (spec::PROGRAM_START + self.here) as u16
+    }
+
+    fn emit(&mut self, opcode: Opcode) {
+        self.emit_word(opcode.encode());
+    }
+
+    fn emit_word(&mut self, word: u16) {
+        let [high, low] = word.to_be_bytes();
+        self.put(high);
+        self.put(low);
+    }
+
+    fn emit_byte(&mut self, value: f64) -> CompileResult<()> {
+        let byte = to_byte(value)?;
+        self.put(byte);
+        Ok(())
+    }
+
+    fn put(&mut self, byte: u8) {
+        if self.here >= self.rom.len() {
+            self.rom.resize(self.here + 1, 0);
+        }
+        self.rom[self.here] = byte;
+        self.here += 1;
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Bytes may be given signed
+fn to_byte(value: f64) -> CompileResult<u8> {
+    let value = value as i64;
+    if !(-128..=255).contains(&value) {
+        return Err(format!("{} does not fit in a byte", value));
+    }
+    Ok(value as u8)
+}
+
+fn to_address(addr: i64, slot: Slot) -> CompileResult<u16> {
+    let max = if slot == Slot::Long { 0xffff } else { 0xfff };
+    if !(0..=max).contains(&addr) {
+        return Err(format!("address {:x} out of range", addr));
+    }
+    Ok(addr as u16)
+}
+
+/// Fills in the address of the instruction at offset
+fn patch(rom: &mut [u8], offset: usize, addr: u16) -> CompileResult<()> {
+    let addr = to_address(addr as i64, Slot::Address)?;
+    rom[offset] |= (addr >> 8) as u8;
+    rom[offset + 1] = addr as u8;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::Machine;
+
+    fn compiles_to(source: &str, bytes: &[u8]) {
+        assert_eq!(compile(source, "test.8o").unwrap(), bytes);
+    }
+
+    fn error_of(source: &str) -> (usize, String) {
+        match compile(source, "test.8o") {
+            Err(Chip8Error::Syntax { line, reason, .. }) => (line, reason),
+            other => panic!("expected a syntax error, got {:?}", other.map_err(|err| err.to_string())),
+        }
+    }
+
+    /// Runs a rom until the PC leaves it and returns V1
+    fn run(rom: &[u8], v0: u8, v2: u8) -> u8 {
+        let mut machine = Machine::new();
+        machine.load_rom(rom).unwrap();
+        machine.set_vx(0, v0);
+        machine.set_vx(2, v2);
+        while (machine.get_pc() as usize) < spec::PROGRAM_START + rom.len() {
+            machine.step().unwrap();
+        }
+        machine.get_vx(1)
+    }
+
+    #[test]
+    fn register_operations() {
+        compiles_to(": main v0 := 5 v1 += 1 v2 := v0 v2 -= v1 i := main",
+                    &[0x60, 0x05, 0x71, 0x01, 0x82, 0x00, 0x82, 0x15, 0xa2, 0x00]);
+    }
+
+    #[test]
+    fn jumps_to_main_unless_it_comes_first() {
+        compiles_to(": main return", &[0x00, 0xee]);
+        compiles_to(": data 1 2 3\n: main i := data", &[0x12, 0x05, 0x01, 0x02, 0x03, 0xa2, 0x02]);
+    }
+
+    #[test]
+    fn constants_and_calc() {
+        compiles_to(":const N 4\n: main v0 := N :calc M { N * 2 } v1 := M", &[0x60, 0x04, 0x61, 0x08]);
+    }
+
+    #[test]
+    fn equality_tests_skip() {
+        compiles_to(": main if v0 == 3 then v1 := 1", &[0x40, 0x03, 0x61, 0x01]);
+        compiles_to(": main if v0 != v2 then v1 := 1", &[0x50, 0x20, 0x61, 0x01]);
+    }
+
+    #[test]
+    fn comparisons_subtract_into_vf() {
+        compiles_to(": main if v2 < v3 then v1 := 1", &[0x8f, 0x30, 0x8f, 0x27, 0x3f, 0x01, 0x61, 0x01]);
+        compiles_to(": main if v2 >= 7 begin v1 := 1 else v1 := 2 end",
+                    &[0x6f, 0x07, 0x8f, 0x27, 0x3f, 0x01, 0x12, 0x0c, 0x61, 0x01, 0x12, 0x0e, 0x61, 0x02]);
+    }
+
+    #[test]
+    fn comparisons_hold_at_the_boundaries() {
+        for &operator in ["<", ">", "<=", ">="].iter() {
+            let source = format!(": main v1 := 0 if v0 {} v2 then v1 := 1", operator);
+            let rom = compile(&source, "test.8o").unwrap();
+            for &(a, b) in [(0, 0), (3, 4), (4, 3), (7, 7), (0, 255), (255, 0), (255, 255)].iter() {
+                let expected = match operator {
+                    "<" => a < b,
+                    ">" => a > b,
+                    "<=" => a <= b,
+                    _ => a >= b,
+                };
+                assert_eq!(run(&rom, a, b) == 1, expected, "{} {} {}", a, operator, b);
+            }
+        }
+    }
+
+    #[test]
+    fn loops() {
+        compiles_to(": main loop v0 += 1 while v0 != 10 again",
+                    &[0x70, 0x01, 0x40, 0x0a, 0x12, 0x08, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(error_of(": main\n  v0 := 1\n  v0 +=\n"), (3, "unexpected end of file".to_string()));
+        assert_eq!(error_of(": main\njump nowhere"), (2, "undefined label: nowhere".to_string()));
+        assert_eq!(error_of(": main v0 := 300"), (1, "300 does not fit in a byte".to_string()));
+    }
+}
@@ -0,0 +1,55 @@
+use quirks::Quirks;
+use spec;
+
+/// The flavour of Chip-8 a rom was written for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Platform {
+    /// The original Chip-8
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1
+    Schip,
+    /// XO-CHIP, as introduced by Octo
+    XoChip,
+}
+
+/// Names accepted by `Platform::from_name`
+pub const PLATFORM_NAMES: [&str; 3] = ["chip8", "schip", "xochip"];
+
+impl Platform {
+    /// Looks up a platform by name
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name {
+            "chip8" => Some(Platform::Chip8),
+            "schip" => Some(Platform::Schip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    /// The name `from_name` looks the platform up by
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::Schip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    /// Size of the addressable memory
+    pub fn mem_size(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::Schip => spec::MEM_SIZE,
+            Platform::XoChip => spec::XO_MEM_SIZE,
+        }
+    }
+
+    /// Quirks roms written for this platform usually expect
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::vip(),
+            Platform::Schip => Quirks::schip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Execution profiler.
+//!
+//! Counts how often each address and each kind of instruction runs, how many
+//! instructions every subroutine takes including the subroutines it calls,
+//! and how many sprites are drawn per frame. Time is measured in
+//! instructions, which is what a rom is limited by at a fixed clock speed.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use error::Result;
+use instr::{self, Opcode};
+use machine::Machine;
+
+/// How many of the hottest addresses are reported
+const HOTTEST: usize = 20;
+
+/// Calls made to a subroutine
+#[derive(Clone, Copy, Default)]
+struct Subroutine {
+    calls: u64,
+    /// Instructions run from the CALL to the RET, both included
+    instructions: u64,
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    instructions: u64,
+    addresses: BTreeMap<u16, u64>,
+    classes: BTreeMap<&'static str, u64>,
+    subroutines: BTreeMap<u16, Subroutine>,
+    /// Subroutines being run, with the instruction count at their CALL
+    calls: Vec<(u16, u64)>,
+    /// Sprites drawn on each frame
+    draws: Vec<u32>,
+    frame_draws: u32,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    /// Counts an instruction about to be executed at addr
+    pub fn record(&mut self, addr: u16, opcode: Opcode) {
+        self.instructions += 1;
+        *self.addresses.entry(addr).or_insert(0) += 1;
+        *self.classes.entry(opcode.class()).or_insert(0) += 1;
+
+        match opcode {
+            Opcode::Call { nnn } => self.calls.push((nnn, self.instructions - 1)),
+            Opcode::Ret => {
+                // Roms that unwind the stack by hand leave calls open
+                if let Some((addr, start)) = self.calls.pop() {
+                    let subroutine = self.subroutines.entry(addr).or_default();
+                    subroutine.calls += 1;
+                    subroutine.instructions += self.instructions - start;
+                }
+            }
+            Opcode::Drw { .. } => self.frame_draws += 1,
+            _ => {}
+        }
+    }
+
+    /// Called at the end of every frame
+    pub fn end_frame(&mut self) {
+        self.draws.push(self.frame_draws);
+        self.frame_draws = 0;
+    }
+
+    /// Addresses by execution count, most executed first
+    fn hottest(&self) -> Vec<(u16, u64)> {
+        let mut addresses: Vec<(u16, u64)> = self.addresses.iter().map(|(&addr, &count)| (addr, count)).collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses.truncate(HOTTEST);
+        addresses
+    }
+
+    fn classes_by_count(&self) -> Vec<(&'static str, u64)> {
+        let mut classes: Vec<(&'static str, u64)> = self.classes.iter().map(|(&class, &count)| (class, count)).collect();
+        classes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        classes
+    }
+
+    fn max_draws(&self) -> u32 {
+        self.draws.iter().cloned().max().unwrap_or(0)
+    }
+
+    fn average_draws(&self) -> f64 {
+        if self.draws.is_empty() {
+            return 0.0;
+        }
+        self.draws.iter().map(|&draws| draws as f64).sum::<f64>() / self.draws.len() as f64
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        100.0 * count as f64 / self.instructions.max(1) as f64
+    }
+
+    /// Writes a readable report, disassembling from the machine's memory
+    pub fn write_report(&self, machine: &Machine, w: &mut dyn Write) -> Result<()> {
+        writeln!(w, "Instructions: {} over {} frames", self.instructions, self.draws.len())?;
+
+        writeln!(w, "\nHottest addresses:")?;
+        for (addr, count) in self.hottest() {
+            writeln!(w,
+                     "{:>12} {:>6.2}% {:04x}: {}",
+                     count,
+                     self.percent(count),
+                     addr,
+                     mnemonic_at(machine, addr))?;
+        }
+
+        writeln!(w, "\nInstructions:")?;
+        for (class, count) in self.classes_by_count() {
+            writeln!(w, "{:>12} {:>6.2}% {}", count, self.percent(count), class)?;
+        }
+
+        writeln!(w, "\nSubroutines (instructions including calls):")?;
+        for (addr, subroutine) in self.subroutines.iter() {
+            writeln!(w,
+                     "{:04x}: {} calls, {} instructions ({:.2}%), {:.1} per call",
+                     addr,
+                     subroutine.calls,
+                     subroutine.instructions,
+                     self.percent(subroutine.instructions),
+                     subroutine.instructions as f64 / subroutine.calls as f64)?;
+        }
+
+        writeln!(w, "\nSprites drawn per frame: {:.2} on average, {} at most",
+                 self.average_draws(),
+                 self.max_draws())?;
+        Ok(())
+    }
+
+    /// Writes the profile as JSON. Addresses are numbers.
+    pub fn write_json(&self, machine: &Machine, w: &mut dyn Write) -> Result<()> {
+        let hottest: Vec<String> = self.hottest()
+            .into_iter()
+            .map(|(addr, count)| {
+                format!("{{\"addr\": {}, \"count\": {}, \"mnemonic\": \"{}\"}}",
+                        addr,
+                        count,
+                        mnemonic_at(machine, addr))
+            })
+            .collect();
+        let classes: Vec<String> = self.classes_by_count()
+            .into_iter()
+            .map(|(class, count)| format!("{{\"class\": \"{}\", \"count\": {}}}", class, count))
+            .collect();
+        let subroutines: Vec<String> = self.subroutines
+            .iter()
+            .map(|(addr, subroutine)| {
+                format!("{{\"addr\": {}, \"calls\": {}, \"instructions\": {}}}",
+                        addr,
+                        subroutine.calls,
+                        subroutine.instructions)
+            })
+            .collect();
+        let draws: Vec<String> = self.draws.iter().map(|draws| draws.to_string()).collect();
+
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"instructions\": {},", self.instructions)?;
+        writeln!(w, "  \"hottest\": [\n    {}\n  ],", hottest.join(",\n    "))?;
+        writeln!(w, "  \"classes\": [\n    {}\n  ],", classes.join(",\n    "))?;
+        writeln!(w, "  \"subroutines\": [\n    {}\n  ],", subroutines.join(",\n    "))?;
+        writeln!(w, "  \"draws_per_frame\": [{}]", draws.join(", "))?;
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+}
+
+/// The instruction now in memory at addr
+fn mnemonic_at(machine: &Machine, addr: u16) -> String {
+    match machine.read_mem(addr as usize, 2) {
+        Ok(bytes) => {
+            let raw = (bytes[0] as u16) << 8 | bytes[1] as u16;
+            instr::decode(raw).map(|opcode| opcode.to_string()).unwrap_or_else(|_| "??".to_string())
+        }
+        Err(_) => "??".to_string(),
+    }
+}
@@ -0,0 +1,124 @@
+/// Behaviours in which Chip-8 interpreters disagree.
+///
+/// The original COSMAC VIP interpreter, CHIP-48 and SUPER-CHIP each implement
+/// a few instructions differently, and roms are written against one of them.
+/// All quirks off matches this emulator's historical behaviour.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8xy6`/`8xyE` shift Vy into Vx, instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    /// `Fx55`/`Fx65` leave I pointing past the last register transferred
+    pub load_store_increments_i: bool,
+    /// `Bnnn` jumps to nnn + Vx, x being the highest nibble of nnn, instead of
+    /// nnn + V0
+    pub jump_uses_vx: bool,
+    /// Sprites are clipped at the edges of the screen instead of wrapping
+    /// around to the opposite side
+    pub clip_sprites: bool,
+    /// `8xy1`/`8xy2`/`8xy3` reset VF to 0
+    pub vf_reset: bool,
+    /// `Dxyn` waits for the next frame, allowing at most one draw per frame
+    pub display_wait: bool,
+}
+
+/// Names accepted by `Quirks::set`, in declaration order
+pub const QUIRK_NAMES: [&str; 6] = [
+    "shift-vy",
+    "load-store-i",
+    "jump-vx",
+    "clip",
+    "vf-reset",
+    "display-wait",
+];
+
+/// Names accepted by `Quirks::preset`
+pub const PRESET_NAMES: [&str; 5] = ["default", "vip", "chip48", "schip", "xochip"];
+
+impl Quirks {
+    /// Behaviour of the original COSMAC VIP interpreter
+    pub fn vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            vf_reset: true,
+            display_wait: true,
+        }
+    }
+
+    /// Behaviour of CHIP-48, on the HP-48 calculators
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    /// Behaviour of SUPER-CHIP 1.1
+    pub fn schip() -> Quirks {
+        Quirks::chip48()
+    }
+
+    /// Behaviour of XO-CHIP, as implemented by Octo
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    /// Looks up a preset by name
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name {
+            "default" => Some(Quirks::default()),
+            "vip" | "chip8" => Some(Quirks::vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::schip()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+
+    /// Names of the quirks turned on, in declaration order
+    pub fn enabled(&self) -> Vec<&'static str> {
+        let values = [
+            self.shift_uses_vy,
+            self.load_store_increments_i,
+            self.jump_uses_vx,
+            self.clip_sprites,
+            self.vf_reset,
+            self.display_wait,
+        ];
+        QUIRK_NAMES.iter()
+            .zip(values.iter())
+            .filter(|&(_, &on)| on)
+            .map(|(&name, _)| name)
+            .collect()
+    }
+
+    /// Turns a single quirk on or off by name. Returns false if there is no
+    /// quirk with that name.
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        let quirk = match name {
+            "shift-vy" => &mut self.shift_uses_vy,
+            "load-store-i" => &mut self.load_store_increments_i,
+            "jump-vx" => &mut self.jump_uses_vx,
+            "clip" => &mut self.clip_sprites,
+            "vf-reset" => &mut self.vf_reset,
+            "display-wait" => &mut self.display_wait,
+            _ => return false,
+        };
+
+        *quirk = value;
+        true
+    }
+}
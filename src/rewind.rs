@@ -0,0 +1,201 @@
+//! Rewind buffer.
+//!
+//! Keeps the save state of the latest frame in full, and the frames before it
+//! as compressed deltas: each delta is the XOR of two consecutive save states,
+//! which is mostly zeros, run length encoded. Stepping back one frame undoes
+//! the newest delta.
+
+use std::collections::VecDeque;
+
+/// Ring buffer of the last frames, newest last
+pub struct Rewind {
+    capacity: usize,
+    latest: Vec<u8>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Keep at most `capacity` frames before the latest one
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity,
+            latest: vec![],
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Number of frames that can be stepped back
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Forgets every frame
+    pub fn clear(&mut self) {
+        self.latest.clear();
+        self.deltas.clear();
+    }
+
+    /// Records the save state of a new frame, dropping the oldest frame if
+    /// the buffer is full
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        // States of different sizes cannot be diffed; start over
+        if self.latest.len() != state.len() {
+            self.deltas.clear();
+        } else {
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(encode(&self.latest, &state));
+        }
+
+        self.latest = state;
+    }
+
+    /// Steps back one frame, returning its save state
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        decode(&delta, &mut self.latest);
+        Some(&self.latest)
+    }
+}
+
+/// Compresses the XOR of two states of the same size, as a sequence of
+/// (zero run, literal count, literals), the counts stored as varints
+fn encode(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = old.iter().zip(new.iter()).map(|(a, b)| a ^ b).collect();
+    let mut out = vec![];
+
+    let mut pos = 0;
+    while pos < xor.len() {
+        let zeros = xor[pos..].iter().take_while(|&&byte| byte == 0).count();
+        pos += zeros;
+
+        let literals = xor[pos..].iter().take_while(|&&byte| byte != 0).count();
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literals);
+        out.extend_from_slice(&xor[pos..(pos + literals)]);
+        pos += literals;
+    }
+
+    out
+}
+
+/// Applies a delta made by `encode` to a state, in place
+fn decode(delta: &[u8], state: &mut [u8]) {
+    let mut input = delta;
+    let mut pos = 0;
+
+    while !input.is_empty() {
+        pos += read_varint(&mut input);
+        let literals = read_varint(&mut input);
+
+        for (byte, xor) in state[pos..(pos + literals)].iter_mut().zip(input.iter()) {
+            *byte ^= xor;
+        }
+        input = &input[literals..];
+        pos += literals;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    while let Some((&byte, rest)) = input.split_first() {
+        *input = rest;
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::Machine;
+
+    fn state_of(machine: &Machine) -> Vec<u8> {
+        let mut state = vec![];
+        machine.save_state(&mut state).unwrap();
+        state
+    }
+
+    #[test]
+    fn pop_restores_the_states_pushed() {
+        // ADD V0, 1; LD F, V0; DRW V1, V1, 5; JP 200
+        let mut machine = Machine::new();
+        machine.load_rom(&[0x70, 0x01, 0xf0, 0x29, 0xd1, 0x15, 0x12, 0x00]).unwrap();
+        let mut rewind = Rewind::new(10);
+        let mut states = vec![];
+        for _ in 0..8 {
+            for _ in 0..4 {
+                machine.step().unwrap();
+            }
+            states.push(state_of(&machine));
+            rewind.push(state_of(&machine));
+        }
+        assert_eq!(rewind.len(), 7);
+
+        states.pop();
+        while let Some(state) = states.pop() {
+            assert_eq!(rewind.pop(), Some(&state[..]));
+        }
+        assert_eq!(rewind.pop(), None);
+
+        let mut restored = Machine::new();
+        restored.load_state(&mut &rewind.latest[..]).unwrap();
+        assert_eq!(state_of(&restored), rewind.latest);
+    }
+
+    #[test]
+    fn push_drops_the_oldest_frame_when_full() {
+        let mut rewind = Rewind::new(2);
+        for frame in 0..5u8 {
+            rewind.push(vec![frame, 0, frame, frame]);
+        }
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.pop(), Some(&[3, 0, 3, 3][..]));
+        assert_eq!(rewind.pop(), Some(&[2, 0, 2, 2][..]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn states_of_another_size_start_over() {
+        let mut rewind = Rewind::new(4);
+        rewind.push(vec![1; 4]);
+        rewind.push(vec![2; 4]);
+        rewind.push(vec![3; 6]);
+        assert!(rewind.is_empty());
+        rewind.push(vec![4; 6]);
+        assert_eq!(rewind.pop(), Some(&[3; 6][..]));
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for &value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, 1 << 20].iter() {
+            let mut out = vec![];
+            write_varint(&mut out, value);
+            assert_eq!(read_varint(&mut &out[..]), value);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Random number generation for `Cxkk`.
+//!
+//! The generator is owned by the machine and fully determined by its seed, so
+//! a run can be reproduced, and its state goes into save states.
+
+use std::io::{Read, Write};
+
+use rand;
+
+use error::Result;
+use state;
+
+/// Seeded random byte generator, a xorshift64* advanced once per `Cxkk`
+#[derive(Clone)]
+pub struct Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng {
+            seed,
+            // xorshift gets stuck on 0
+            state: if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed },
+        }
+    }
+
+    /// A generator with a seed picked at random
+    pub fn from_entropy() -> Rng {
+        Rng::new(rand::random())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Produces the next random byte
+    pub fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+    }
+
+    /// Writes the seed and the current position
+    pub fn save_state(&self, w: &mut dyn Write) -> Result<()> {
+        state::write_u32(w, (self.seed >> 32) as u32)?;
+        state::write_u32(w, self.seed as u32)?;
+        state::write_u32(w, (self.state >> 32) as u32)?;
+        state::write_u32(w, self.state as u32)
+    }
+
+    /// Reads a generator written by `save_state`
+    pub fn load_state(r: &mut dyn Read) -> Result<Rng> {
+        let seed = (state::read_u32(r)? as u64) << 32 | state::read_u32(r)? as u64;
+        let mut rng = Rng::new(seed);
+        rng.state = (state::read_u32(r)? as u64) << 32 | state::read_u32(r)? as u64;
+
+        Ok(rng)
+    }
+}
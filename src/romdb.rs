@@ -0,0 +1,340 @@
+//! Per-rom settings.
+//!
+//! Roms are told apart by the SHA-1 of their file. Their settings come from a
+//! database bundled with the emulator, `db/programs.json`, in the format of
+//! the community chip-8-database, and from a user TOML file that overrides
+//! it, with a table per rom:
+//!
+//! ```toml
+//! [0f1e2d3c4b5a69788796a5b4c3d2e1f00f1e2d3c]
+//! title = "Some game"
+//! platform = "schip"
+//! quirks = "chip48"
+//! ips = 1200
+//! palette = ["#101010", "#e0e0e0"]
+//! keymap = "x123qweasdzc4rfv"
+//!
+//! [0f1e2d3c4b5a69788796a5b4c3d2e1f00f1e2d3c.quirk]
+//! clip = false
+//!
+//! [0f1e2d3c4b5a69788796a5b4c3d2e1f00f1e2d3c.keys]
+//! 5 = ["w", "Up"]
+//! ```
+//!
+//! `keys` binds host keys as a bindings file of the `keymap` module does,
+//! over the 16 keys of `keymap`.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{self, Value as Json};
+use toml::Value as Toml;
+
+use error::{Chip8Error, Result};
+use keymap::KeyMap;
+use platform::Platform;
+use quirks::Quirks;
+use spec;
+
+/// The bundled database, a chip-8-database `programs.json`
+const BUNDLED: &str = include_str!("../db/programs.json");
+
+/// Settings a rom should run with. The ones not set keep their defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RomConfig {
+    pub title: Option<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub ips: Option<u32>,
+    pub palette: Option<[u32; 16]>,
+    pub keymap: Option<KeyMap>,
+}
+
+impl RomConfig {
+    /// Settings of the rom with the given hash in the bundled database, with
+    /// the overrides in the TOML file at path applied
+    pub fn lookup(hash: &str, overrides: Option<&Path>) -> Result<RomConfig> {
+        let mut config = RomConfig::from_database(BUNDLED, "db/programs.json", hash)?;
+        if let Some(path) = overrides {
+            let source = fs::read_to_string(path)?;
+            config.apply_overrides(&source, &path.display().to_string(), hash)?;
+        }
+        Ok(config)
+    }
+
+    /// Settings of the rom with the given hash in a chip-8-database
+    /// `programs.json`, named file in errors
+    pub fn from_database(json: &str, file: &str, hash: &str) -> Result<RomConfig> {
+        let programs: Json = serde_json::from_str(json).map_err(|err| invalid(file, err.to_string()))?;
+        let programs = programs.as_array().ok_or_else(|| invalid(file, "expected a list of programs".to_string()))?;
+
+        for program in programs {
+            let rom = match program.get("roms").and_then(|roms| roms.get(hash)) {
+                Some(rom) => rom,
+                None => continue,
+            };
+
+            let mut config = RomConfig {
+                title: program.get("title").and_then(Json::as_str).map(str::to_string),
+                ..RomConfig::default()
+            };
+
+            // The first of the rom's platforms that is emulated
+            let platforms = rom.get("platforms").and_then(Json::as_array).map(Vec::as_slice).unwrap_or_default();
+            for id in platforms.iter().filter_map(Json::as_str) {
+                if let Some((platform, mut quirks)) = database_platform(id) {
+                    if let Some(quirky) = rom.get("quirkyPlatforms").and_then(|quirky| quirky.get(id)) {
+                        apply_database_quirks(&mut quirks, quirky);
+                    }
+                    config.platform = Some(platform);
+                    config.quirks = Some(quirks);
+                    break;
+                }
+            }
+
+            // The tick rate is in instructions per frame
+            if let Some(tickrate) = rom.get("tickrate").and_then(Json::as_u64) {
+                config.ips = Some(tickrate as u32 * spec::FPS as u32);
+            }
+
+            if let Some(pixels) = rom.get("colors").and_then(|colors| colors.get("pixels")).and_then(Json::as_array) {
+                let colors: Vec<&str> = pixels.iter().filter_map(Json::as_str).collect();
+                config.palette = Some(palette(&colors).ok_or_else(|| invalid(file, format!("invalid colours for {}", hash)))?);
+            }
+
+            return Ok(config);
+        }
+
+        Ok(RomConfig::default())
+    }
+
+    /// Applies the settings in the table named after the hash in a TOML
+    /// override file, named file in errors
+    pub fn apply_overrides(&mut self, source: &str, file: &str, hash: &str) -> Result<()> {
+        let root: Toml = source.parse().map_err(|err: ::toml::de::Error| invalid(file, err.to_string()))?;
+        let settings = match root.as_table().and_then(|roms| roms.iter().find(|&(key, _)| key.eq_ignore_ascii_case(hash))) {
+            Some((_, settings)) => settings.as_table().ok_or_else(|| invalid(file, format!("{} is not a table", hash)))?,
+            None => return Ok(()),
+        };
+
+        for (key, value) in settings.iter() {
+            match key.as_str() {
+                "title" => self.title = Some(string(file, key, value)?.to_string()),
+                "platform" => {
+                    let name = string(file, key, value)?;
+                    let platform = Platform::from_name(name)
+                        .ok_or_else(|| invalid(file, format!("unknown platform: {}", name)))?;
+                    self.platform = Some(platform);
+                    // A platform brings its quirks, unless they are given too
+                    if !settings.contains_key("quirks") {
+                        self.quirks = Some(platform.quirks());
+                    }
+                }
+                "quirks" => {
+                    let name = string(file, key, value)?;
+                    self.quirks = Some(Quirks::preset(name)
+                        .ok_or_else(|| invalid(file, format!("unknown quirks preset: {}", name)))?);
+                }
+                "quirk" | "keys" => {}
+                "ips" => {
+                    let ips = value.as_integer()
+                        .filter(|&ips| ips > 0 && ips <= u32::MAX as i64)
+                        .ok_or_else(|| invalid(file, "ips must be a positive number".to_string()))?;
+                    self.ips = Some(ips as u32);
+                }
+                "palette" => {
+                    let colors: Vec<&str> = value.as_array()
+                        .map(|colors| colors.iter().filter_map(Toml::as_str).collect())
+                        .unwrap_or_default();
+                    self.palette = Some(palette(&colors)
+                        .ok_or_else(|| invalid(file, "palette must be a list of up to 16 colours".to_string()))?);
+                }
+                "keymap" => {
+                    let layout = string(file, key, value)?;
+                    let keymap = KeyMap::from_layout(layout)
+                        .ok_or_else(|| invalid(file, "keymap must have 16 keys".to_string()))?;
+                    self.keymap.get_or_insert_with(KeyMap::new).extend(&keymap);
+                }
+                _ => return Err(invalid(file, format!("unknown setting: {}", key))),
+            }
+        }
+
+        // Single quirks apply on top of the preset, or else the platform's
+        if let Some(quirk) = settings.get("quirk") {
+            let quirk = quirk.as_table().ok_or_else(|| invalid(file, "quirk must be a table".to_string()))?;
+            let mut quirks = self.quirks.unwrap_or_else(|| self.platform.unwrap_or_default().quirks());
+            for (name, value) in quirk.iter() {
+                let value = value.as_bool()
+                    .ok_or_else(|| invalid(file, format!("quirk {} must be true or false", name)))?;
+                if !quirks.set(name, value) {
+                    return Err(invalid(file, format!("unknown quirk: {}", name)));
+                }
+            }
+            self.quirks = Some(quirks);
+        }
+
+        // Bindings of single keys apply over the layout
+        if let Some(keys) = settings.get("keys") {
+            let keys = keys.as_table().ok_or_else(|| invalid(file, "keys must be a table".to_string()))?;
+            let keymap = KeyMap::from_toml(keys, file)?;
+            self.keymap.get_or_insert_with(KeyMap::new).extend(&keymap);
+        }
+
+        Ok(())
+    }
+}
+
+/// The directory of the user's configuration files, `chip-8` in
+/// `$XDG_CONFIG_HOME` or `~/.config`
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("chip-8"))
+}
+
+/// The user's override file, `roms.toml` in the configuration directory
+pub fn user_config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("roms.toml"))
+}
+
+/// Parses a 0xRRGGBB colour, also written #RRGGBB or RRGGBB
+pub fn parse_color(color: &str) -> Option<u32> {
+    let hex = color.trim().trim_start_matches("0x").trim_start_matches('#');
+    u32::from_str_radix(hex, 16).ok().filter(|&rgb| hex.len() == 6 && rgb <= 0xffffff)
+}
+
+/// The default palette with its first colours replaced
+fn palette(colors: &[&str]) -> Option<[u32; 16]> {
+    let mut palette = spec::DEFAULT_PALETTE;
+    if colors.len() > palette.len() {
+        return None;
+    }
+    for (color, value) in palette.iter_mut().zip(colors) {
+        *color = parse_color(value)?;
+    }
+    Some(palette)
+}
+
+/// The platform and quirks of a chip-8-database platform id, if emulated
+fn database_platform(id: &str) -> Option<(Platform, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Platform::Chip8, Quirks::vip())),
+        "modernChip8" => Some((Platform::Chip8, Quirks::default())),
+        "chip48" => Some((Platform::Chip8, Quirks::chip48())),
+        "superchip1" | "superchip" => Some((Platform::Schip, Quirks::schip())),
+        "xochip" => Some((Platform::XoChip, Quirks::xochip())),
+        _ => None,
+    }
+}
+
+/// Applies a chip-8-database `quirkyPlatforms` entry
+fn apply_database_quirks(quirks: &mut Quirks, quirky: &Json) {
+    let flag = |name: &str| quirky.get(name).and_then(Json::as_bool);
+
+    if let Some(shift) = flag("shift") {
+        quirks.shift_uses_vy = !shift;
+    }
+    // `memoryIncrementByX` still moves I, if one short, so only leaving it
+    // unchanged turns the quirk off
+    if let Some(unchanged) = flag("memoryLeaveIUnchanged") {
+        quirks.load_store_increments_i = !unchanged;
+    }
+    if let Some(wrap) = flag("wrap") {
+        quirks.clip_sprites = !wrap;
+    }
+    if let Some(jump) = flag("jump") {
+        quirks.jump_uses_vx = jump;
+    }
+    if let Some(vblank) = flag("vblank") {
+        quirks.display_wait = vblank;
+    }
+    if let Some(logic) = flag("logic") {
+        quirks.vf_reset = logic;
+    }
+}
+
+fn string<'a>(file: &str, key: &str, value: &'a Toml) -> Result<&'a str> {
+    value.as_str().ok_or_else(|| invalid(file, format!("{} must be a string", key)))
+}
+
+fn invalid(file: &str, reason: String) -> Chip8Error {
+    Chip8Error::InvalidConfig { file: file.to_string(), reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use movie::rom_hash;
+
+    /// Two programs, as written in the chip-8-database `programs.json`, with
+    /// the hashes of `SCHIP_ROM` and `XOCHIP_ROM` filled in
+    const DATABASE: &str = r##"[
+        {
+            "title": "Jumper",
+            "authors": ["Someone"],
+            "roms": {
+                "SCHIP_HASH": {
+                    "file": "jumper.ch8",
+                    "platforms": ["superchip"],
+                    "quirkyPlatforms": {
+                        "superchip": { "shift": false, "wrap": true }
+                    },
+                    "tickrate": 30
+                }
+            }
+        },
+        {
+            "title": "Planes",
+            "roms": {
+                "XOCHIP_HASH": {
+                    "file": "planes.ch8",
+                    "platforms": ["megachip8", "xochip"],
+                    "colors": { "pixels": ["#000000", "#ff0000", "#00ff00"] }
+                }
+            }
+        }
+    ]"##;
+
+    const SCHIP_ROM: [u8; 4] = [0x00, 0xff, 0x12, 0x02];
+    const XOCHIP_ROM: [u8; 4] = [0xf1, 0x01, 0x12, 0x02];
+
+    fn lookup(rom: &[u8]) -> RomConfig {
+        let database = DATABASE
+            .replace("SCHIP_HASH", &rom_hash(&SCHIP_ROM))
+            .replace("XOCHIP_HASH", &rom_hash(&XOCHIP_ROM));
+        RomConfig::from_database(&database, "programs.json", &rom_hash(rom)).unwrap()
+    }
+
+    #[test]
+    fn hash_resolves_to_platform_and_quirks() {
+        let mut quirks = Quirks::schip();
+        quirks.shift_uses_vy = true;
+        quirks.clip_sprites = false;
+
+        let config = lookup(&SCHIP_ROM);
+        assert_eq!(config.title.as_deref(), Some("Jumper"));
+        assert_eq!(config.platform, Some(Platform::Schip));
+        assert_eq!(config.quirks, Some(quirks));
+        assert_eq!(config.ips, Some(30 * spec::FPS as u32));
+    }
+
+    #[test]
+    fn first_emulated_platform_wins() {
+        let config = lookup(&XOCHIP_ROM);
+        assert_eq!(config.platform, Some(Platform::XoChip));
+        assert_eq!(config.quirks, Some(Quirks::xochip()));
+        assert_eq!(config.palette.map(|palette| palette[..3].to_vec()), Some(vec![0x000000, 0xff0000, 0x00ff00]));
+    }
+
+    #[test]
+    fn unknown_hash_has_no_settings() {
+        assert_eq!(lookup(&[0x12, 0x00]), RomConfig::default());
+    }
+
+    #[test]
+    fn bundled_database_parses() {
+        assert!(RomConfig::from_database(BUNDLED, "db/programs.json", &rom_hash(&[])).is_ok());
+    }
+}
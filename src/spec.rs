@@ -1,11 +1,31 @@
-pub const WINDOW_NAME: &'static str = "Chip-8";
+pub const WINDOW_NAME: &str = "Chip-8";
 
 pub const DISPLAY_WIDTH: u32 = 64;
 pub const DISPLAY_HEIGHT: u32 = 32;
+pub const HIRES_WIDTH: u32 = 128;
+pub const HIRES_HEIGHT: u32 = 64;
 pub const DISPLAY_SCALE: u32 = 10;
 
+/// Colours of the pixels, as 0xRRGGBB, indexed by the mask of lit XO-CHIP planes
+pub const DEFAULT_PALETTE: [u32; 16] = [
+    0x000000, 0xffffff, 0xaaaaaa, 0x555555, 0xff0000, 0x00ff00, 0x0000ff, 0xffff00,
+    0x880000, 0x008800, 0x000088, 0x888800, 0xff00ff, 0x00ffff, 0x880088, 0x008888,
+];
+
 pub const MEM_SIZE: usize = 4096;
+pub const XO_MEM_SIZE: usize = 0x10000;
 pub const PROGRAM_START: usize = 0x200;
+pub const FONT_START: usize = 0x000;
+pub const BIG_FONT_START: usize = 0x050;
 
 pub const FPS: usize = 60;
-pub const MILLI_PER_FRAME: usize = 1000 / FPS;
+pub const DEFAULT_IPS: u32 = 700;
+pub const MAX_FRAMES_PER_TICK: usize = 5;
+pub const REWIND_SECONDS: usize = 30;
+
+pub const BEEP_FREQUENCY: f32 = 440.0;
+pub const BEEP_VOLUME: f32 = 0.25;
+pub const AUDIO_SAMPLE_RATE: i32 = 44100;
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+pub const DEFAULT_PITCH: u8 = 64;
+pub const PATTERN_BASE_RATE: f32 = 4000.0;
@@ -0,0 +1,162 @@
+//! Binary save state format.
+//!
+//! A save state starts with a magic number and a format version, followed by
+//! the fields of the machine in a fixed order. Multi-byte values are stored
+//! big endian, like Chip-8 instructions. `Machine::save_state` and
+//! `Machine::load_state` define the layout; this module holds the primitives
+//! they are written with.
+
+use std::io::{self, Read, Write};
+
+use error::{Chip8Error, Result};
+use platform::Platform;
+use quirks::Quirks;
+
+/// First bytes of every save state
+pub const MAGIC: &[u8; 4] = b"CH8S";
+
+/// Version of the layout written by this build
+pub const VERSION: u8 = 1;
+
+/// Writes the magic number and the current version
+pub fn write_header(w: &mut dyn Write) -> Result<()> {
+    w.write_all(MAGIC)?;
+    write_u8(w, VERSION)
+}
+
+/// Checks the magic number and returns the version of the layout that follows
+pub fn read_header(r: &mut dyn Read) -> Result<u8> {
+    if read_bytes(r, MAGIC.len())? != MAGIC[..] {
+        return Err(invalid("not a save state"));
+    }
+
+    let version = read_u8(r)?;
+    if version == 0 || version > VERSION {
+        return Err(invalid("unsupported version"));
+    }
+
+    Ok(version)
+}
+
+pub fn write_u8(w: &mut dyn Write, value: u8) -> Result<()> {
+    w.write_all(&[value])?;
+    Ok(())
+}
+
+pub fn write_u16(w: &mut dyn Write, value: u16) -> Result<()> {
+    w.write_all(&[(value >> 8) as u8, value as u8])?;
+    Ok(())
+}
+
+pub fn write_u32(w: &mut dyn Write, value: u32) -> Result<()> {
+    write_u16(w, (value >> 16) as u16)?;
+    write_u16(w, value as u16)
+}
+
+pub fn write_bool(w: &mut dyn Write, value: bool) -> Result<()> {
+    write_u8(w, value as u8)
+}
+
+/// Writes a length prefixed byte buffer
+pub fn write_bytes(w: &mut dyn Write, bytes: &[u8]) -> Result<()> {
+    write_u32(w, bytes.len() as u32)?;
+    w.write_all(bytes)?;
+    Ok(())
+}
+
+pub fn read_u8(r: &mut dyn Read) -> Result<u8> {
+    Ok(read_bytes(r, 1)?[0])
+}
+
+pub fn read_u16(r: &mut dyn Read) -> Result<u16> {
+    let bytes = read_bytes(r, 2)?;
+    Ok(((bytes[0] as u16) << 8) | bytes[1] as u16)
+}
+
+pub fn read_u32(r: &mut dyn Read) -> Result<u32> {
+    let high = read_u16(r)? as u32;
+    let low = read_u16(r)? as u32;
+    Ok((high << 16) | low)
+}
+
+pub fn read_bool(r: &mut dyn Read) -> Result<bool> {
+    match read_u8(r)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(invalid("corrupt flag")),
+    }
+}
+
+/// Reads a byte buffer written by `write_bytes`, refusing anything longer
+/// than `max`
+pub fn read_buffer(r: &mut dyn Read, max: usize) -> Result<Vec<u8>> {
+    let len = read_u32(r)? as usize;
+    if len > max {
+        return Err(invalid("buffer too large"));
+    }
+
+    read_bytes(r, len)
+}
+
+/// Reads exactly n bytes
+pub fn read_bytes(r: &mut dyn Read, n: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0u8; n];
+    r.read_exact(&mut bytes).map_err(|err| {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            invalid("truncated")
+        } else {
+            Chip8Error::Io(err)
+        }
+    })?;
+
+    Ok(bytes)
+}
+
+pub fn write_platform(w: &mut dyn Write, platform: Platform) -> Result<()> {
+    let value = match platform {
+        Platform::Chip8 => 0,
+        Platform::Schip => 1,
+        Platform::XoChip => 2,
+    };
+    write_u8(w, value)
+}
+
+pub fn read_platform(r: &mut dyn Read) -> Result<Platform> {
+    match read_u8(r)? {
+        0 => Ok(Platform::Chip8),
+        1 => Ok(Platform::Schip),
+        2 => Ok(Platform::XoChip),
+        _ => Err(invalid("unknown platform")),
+    }
+}
+
+/// Writes the quirks as a bit set, in declaration order
+pub fn write_quirks(w: &mut dyn Write, quirks: Quirks) -> Result<()> {
+    let flags = [quirks.shift_uses_vy,
+                 quirks.load_store_increments_i,
+                 quirks.jump_uses_vx,
+                 quirks.clip_sprites,
+                 quirks.vf_reset,
+                 quirks.display_wait];
+    let bits = flags.iter().enumerate().fold(0u8, |bits, (i, &flag)| bits | (flag as u8) << i);
+    write_u8(w, bits)
+}
+
+pub fn read_quirks(r: &mut dyn Read) -> Result<Quirks> {
+    let bits = read_u8(r)?;
+    let flag = |i: u8| bits & (1 << i) != 0;
+
+    Ok(Quirks {
+        shift_uses_vy: flag(0),
+        load_store_increments_i: flag(1),
+        jump_uses_vx: flag(2),
+        clip_sprites: flag(3),
+        vf_reset: flag(4),
+        display_wait: flag(5),
+    })
+}
+
+/// A save state that cannot be loaded
+pub fn invalid(reason: &'static str) -> Chip8Error {
+    Chip8Error::InvalidState { reason }
+}
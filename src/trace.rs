@@ -0,0 +1,110 @@
+//! Execution traces.
+//!
+//! A trace has one JSON object per line for every instruction executed: the
+//! instruction count and frame it ran at, its address, raw opcode and
+//! mnemonic, and the registers it changed, with their new values:
+//!
+//! ```text
+//! {"cycle":0,"frame":0,"pc":"0200","opcode":"6001","mnemonic":"LD V0, 01","changes":{"v0":1}}
+//! {"cycle":1,"frame":0,"pc":"0202","opcode":"a20a","mnemonic":"LD I, 20a","changes":{"i":522}}
+//! ```
+//!
+//! Traces of two runs can be compared line by line to find where they part.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use error::Result;
+use instr::Opcode;
+use machine::Machine;
+
+/// The registers a trace follows
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Registers {
+    vx: [u8; 16],
+    i: u16,
+    sp: u8,
+    dt: u8,
+    st: u8,
+}
+
+impl Registers {
+    fn of(machine: &Machine) -> Registers {
+        let mut vx = [0; 16];
+        for (index, value) in vx.iter_mut().enumerate() {
+            *value = machine.get_vx(index);
+        }
+
+        Registers {
+            vx,
+            i: machine.get_i(),
+            sp: machine.get_sp(),
+            dt: machine.get_dt(),
+            st: machine.get_st(),
+        }
+    }
+}
+
+/// Writes a trace record for every instruction executed
+pub struct Tracer {
+    out: Box<dyn Write>,
+    /// The registers before the current instruction
+    before: Option<Registers>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>) -> Tracer {
+        Tracer { out, before: None }
+    }
+
+    /// A tracer writing to a new file at path
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Tracer> {
+        Ok(Tracer::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    /// Called before an instruction is executed
+    pub fn begin(&mut self, machine: &Machine) {
+        self.before = Some(Registers::of(machine));
+    }
+
+    /// Called once the instruction is executed, to write its record
+    pub fn end(&mut self, cycle: u64, frame: u64, pc: u16, raw: u16, opcode: Opcode, machine: &Machine) -> Result<()> {
+        let after = Registers::of(machine);
+        let before = self.before.take().unwrap_or(after);
+
+        let mut changes = vec![];
+        for (index, (old, new)) in before.vx.iter().zip(after.vx.iter()).enumerate() {
+            if old != new {
+                changes.push(format!("\"v{:x}\":{}", index, new));
+            }
+        }
+        if before.i != after.i {
+            changes.push(format!("\"i\":{}", after.i));
+        }
+        if before.sp != after.sp {
+            changes.push(format!("\"sp\":{}", after.sp));
+        }
+        if before.dt != after.dt {
+            changes.push(format!("\"dt\":{}", after.dt));
+        }
+        if before.st != after.st {
+            changes.push(format!("\"st\":{}", after.st));
+        }
+
+        writeln!(self.out,
+                 "{{\"cycle\":{},\"frame\":{},\"pc\":\"{:04x}\",\"opcode\":\"{:04x}\",\"mnemonic\":\"{}\",\"changes\":{{{}}}}}",
+                 cycle,
+                 frame,
+                 pc,
+                 raw,
+                 opcode,
+                 changes.join(","))?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}